@@ -1,19 +1,33 @@
-use std::{collections::HashMap, fs, io, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 use typst::{
     foundations::Bytes,
-    syntax::{FileId, RootedPath, Source, VirtualPath, VirtualRoot},
+    syntax::{FileId, RootedPath, Source, VirtualPath, VirtualRoot, package::PackageSpec},
     text::Font,
 };
 
-use crate::error::AppResult;
-use tracing::{debug, trace};
+use crate::{
+    catalog::{TEMPLATE_META_EXTENSION, TEMPLATE_SCHEMA_EXTENSION},
+    error::AppResult,
+    pdf::TEMPLATE_OPTIONS_EXTENSION,
+};
+use tracing::{debug, trace, warn};
 
 /// Build a project-rooted [`FileId`] from a (relative) path.
 pub(crate) fn file_id_from_path(path: &Path) -> AppResult<FileId> {
+    file_id_in_root(VirtualRoot::Project, path)
+}
+
+/// Build a [`FileId`] for a (relative) path inside the given root.
+fn file_id_in_root(root: VirtualRoot, path: &Path) -> AppResult<FileId> {
     let vpath = VirtualPath::new(path.to_string_lossy())
         .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
-    Ok(FileId::new(RootedPath::new(VirtualRoot::Project, vpath)))
+    Ok(FileId::new(RootedPath::new(root, vpath)))
 }
 
 /// Represents the type of a file based on its extension.
@@ -34,31 +48,67 @@ impl FileType {
 
         match ext.as_deref() {
             None => FileType::Other,
-            Some("ttf") | Some("otf") | Some("woff") | Some("woff2") => FileType::Font,
+            Some("ttf") | Some("otf") | Some("ttc") | Some("otc") | Some("woff")
+            | Some("woff2") => FileType::Font,
             Some("typ") | Some("typst") => FileType::TypstSource,
             _ => FileType::Other,
         }
     }
 }
 
+/// Whether `path` has the extension of a font file.
+pub(crate) fn is_font_file(path: &Path) -> bool {
+    matches!(FileType::from_path(path), FileType::Font)
+}
+
+/// Whether `path` is a template sidecar such as `invoice.meta.json`.
+fn is_sidecar(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    [
+        TEMPLATE_OPTIONS_EXTENSION,
+        TEMPLATE_META_EXTENSION,
+        TEMPLATE_SCHEMA_EXTENSION,
+    ]
+    .iter()
+    .any(|extension| {
+        name.strip_suffix(extension)
+            .is_some_and(|stem| stem.len() > 1 && stem.ends_with('.'))
+    })
+}
+
 /// Aggregated Typst sources, binary assets, and fonts discovered on disk.
 #[derive(Default)]
 pub struct Assets {
     pub sources: Vec<Source>,
     pub assets: HashMap<FileId, Bytes>,
-    pub fonts: Vec<Font>,
+    /// Font faces with the (relative) file each was read from.
+    pub fonts: Vec<(PathBuf, Font)>,
+    pub packages: HashSet<PackageSpec>,
+    /// Last modification time of each project source, where the file system reports one.
+    pub modified: HashMap<FileId, SystemTime>,
+    /// Binary assets left on disk by [`collect_dir_contents_lazily`], with
+    /// the file each id is read from.
+    pub deferred: HashMap<FileId, PathBuf>,
 }
 
 impl Assets {
     /// Merge another [`Assets`] collection into this one.
-    fn merge(&mut self, other: Assets) {
+    pub(crate) fn merge(&mut self, other: Assets) {
         self.sources.extend(other.sources);
         self.assets.extend(other.assets);
         self.fonts.extend(other.fonts);
+        self.packages.extend(other.packages);
+        self.modified.extend(other.modified);
+        self.deferred.extend(other.deferred);
     }
 
     /// Insert a file into the collection based on its detected [`FileType`].
-    fn add_file(&mut self, path: &Path, relative_path: &Path) -> AppResult<()> {
+    ///
+    /// With `lazy`, binary assets other than template sidecars are left on disk.
+    fn add_file(&mut self, path: &Path, relative_path: &Path, lazy: bool) -> AppResult<()> {
         let file_type = FileType::from_path(path);
         trace!(
             absolute = %path.display(),
@@ -71,19 +121,25 @@ impl Assets {
             FileType::TypstSource => {
                 let content = fs::read_to_string(path)?;
                 let file_id = file_id_from_path(relative_path)?;
+                if let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) {
+                    self.modified.insert(file_id, modified);
+                }
                 self.sources.push(Source::new(file_id, content));
                 debug!(file = %relative_path.display(), "Loaded Typst source file");
             }
             FileType::Font => {
                 let content = fs::read(path)?;
-                if let Some(font) = Font::new(Bytes::new(content), 0) {
-                    debug!(
-                        file = %relative_path.display(),
-                        family = %font.info().family,
-                        "Loaded font file"
-                    );
-                    self.fonts.push(font);
-                }
+                let faces = load_font_faces(Bytes::new(content), relative_path);
+                self.fonts.extend(
+                    faces
+                        .into_iter()
+                        .map(|font| (relative_path.to_path_buf(), font)),
+                );
+            }
+            FileType::Other if lazy && !is_sidecar(path) => {
+                let file_id = file_id_from_path(relative_path)?;
+                self.deferred.insert(file_id, path.to_path_buf());
+                trace!(file = %relative_path.display(), "Leaving binary asset on disk");
             }
             FileType::Other => {
                 let content = fs::read(path)?;
@@ -95,6 +151,57 @@ impl Assets {
 
         Ok(())
     }
+
+    /// Insert a file belonging to a package.
+    ///
+    /// Fonts shipped inside packages are not registered with the font book
+    /// (Typst does not do that either); they stay readable as plain files.
+    fn add_package_file(
+        &mut self,
+        spec: &PackageSpec,
+        path: &Path,
+        relative_path: &Path,
+    ) -> AppResult<()> {
+        let file_id = file_id_in_root(VirtualRoot::Package(spec.clone()), relative_path)?;
+        trace!(package = %spec, file = %relative_path.display(), "Processing package file");
+
+        match FileType::from_path(path) {
+            FileType::TypstSource => {
+                let content = fs::read_to_string(path)?;
+                self.sources.push(Source::new(file_id, content));
+            }
+            FileType::Font | FileType::Other => {
+                let content = fs::read(path)?;
+                self.assets.insert(file_id, Bytes::new(content));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse every face of a font file; collections (`.ttc`/`.otc`) hold several.
+///
+/// Faces that fail to parse are logged and skipped.
+pub(crate) fn load_font_faces(data: Bytes, file: &Path) -> Vec<Font> {
+    let count = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+    (0..count)
+        .filter_map(|index| match Font::new(data.clone(), index) {
+            Some(font) => {
+                debug!(
+                    file = %file.display(),
+                    index,
+                    family = %font.info().family,
+                    "Loaded font face"
+                );
+                Some(font)
+            }
+            None => {
+                warn!(file = %file.display(), index, "Skipping font face that failed to parse");
+                None
+            }
+        })
+        .collect()
 }
 
 /// Recursively collect every asset/file within the provided directory tree.
@@ -102,27 +209,122 @@ pub fn collect_dir_contents(dir: impl AsRef<Path>) -> AppResult<Assets> {
     let dir = dir.as_ref();
     debug!(path = %dir.display(), "Scanning asset directory");
     let mut assets = Assets::default();
+    collect_dir_files(&mut assets, dir, false)?;
+    Ok(assets)
+}
 
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
+/// Like [`collect_dir_contents`], but only read sources, fonts, and template
+/// sidecars; other files are listed in [`Assets::deferred`] under the same
+/// ids and stay on disk until a render asks for them.
+pub fn collect_dir_contents_lazily(dir: impl AsRef<Path>) -> AppResult<Assets> {
+    let dir = dir.as_ref();
+    debug!(path = %dir.display(), "Scanning asset directory lazily");
+    let mut assets = Assets::default();
+    collect_dir_files(&mut assets, dir, true)?;
+    Ok(assets)
+}
 
+/// Recursively add every file below `dir`.
+///
+/// Each file is keyed by its path relative to the directory it is in, so
+/// `images/logo.png` is available to templates as `logo.png`.
+fn collect_dir_files(assets: &mut Assets, dir: &Path, lazy: bool) -> AppResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
         if path.is_dir() {
             trace!(path = %path.display(), "Descending into subdirectory");
-            assets.merge(collect_dir_contents(&path)?);
+            let mut nested = Assets::default();
+            collect_dir_files(&mut nested, &path, lazy)?;
+            assets.merge(nested);
         } else if path.is_file() {
             let relative_path = path.strip_prefix(dir).unwrap_or(&path);
-            assets.add_file(&path, relative_path)?;
+            assets.add_file(&path, relative_path, lazy)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect every package from a directory laid out like Typst's package
+/// cache, i.e. `{namespace}/{name}/{version}/`.
+///
+/// Directories whose names do not form a valid package specification are
+/// skipped with a warning.
+pub fn collect_packages(dir: impl AsRef<Path>) -> AppResult<Assets> {
+    let dir = dir.as_ref();
+    debug!(path = %dir.display(), "Scanning package directory");
+    let mut assets = Assets::default();
+
+    for namespace in subdirectories(dir)? {
+        for name in subdirectories(&namespace)? {
+            for version in subdirectories(&name)? {
+                let spec = format!(
+                    "@{}/{}:{}",
+                    dir_name(&namespace),
+                    dir_name(&name),
+                    dir_name(&version)
+                );
+                match spec.parse::<PackageSpec>() {
+                    Ok(spec) => {
+                        collect_package_files(&mut assets, &spec, &version, &version)?;
+                        debug!(package = %spec, "Loaded package");
+                        assets.packages.insert(spec);
+                    }
+                    Err(error) => {
+                        warn!(path = %version.display(), %error, "Skipping invalid package directory");
+                    }
+                }
+            }
         }
     }
 
     Ok(assets)
 }
 
+/// Recursively add every file below `dir` to the package `spec` rooted at `root`.
+fn collect_package_files(
+    assets: &mut Assets,
+    spec: &PackageSpec,
+    root: &Path,
+    dir: &Path,
+) -> AppResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_package_files(assets, spec, root, &path)?;
+        } else if path.is_file() {
+            let relative_path = path.strip_prefix(root).unwrap_or(&path);
+            assets.add_package_file(spec, &path, relative_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// List the direct subdirectories of `dir`.
+fn subdirectories(dir: &Path) -> AppResult<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    Ok(dirs)
+}
+
+/// The final component of a directory path as a string.
+fn dir_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{collect_dir_contents, file_id_from_path};
+    use super::{collect_dir_contents, file_id_from_path, load_font_faces};
     use std::path::Path;
+    use typst::foundations::Bytes;
 
     /// Ensure the assets directory exposes the expected sources, assets, and fonts.
     #[test]
@@ -148,10 +350,50 @@ mod tests {
         let has_bagnard_font = assets
             .fonts
             .iter()
-            .any(|font| font.info().family == "Bagnard");
+            .any(|(_, font)| font.info().family == "Bagnard");
         assert!(
             has_bagnard_font,
             "expected Bagnard.otf to be loaded into the font collection"
         );
     }
+
+    /// Wrap a single font into a collection whose faces start at `offsets`,
+    /// relative to the end of the collection header.
+    fn font_collection(font: &[u8], offsets: &[u32]) -> Vec<u8> {
+        let header_len = 12 + 4 * offsets.len() as u32;
+        let mut data = b"ttcf".to_vec();
+        data.extend(0x0001_0000u32.to_be_bytes());
+        data.extend((offsets.len() as u32).to_be_bytes());
+        for offset in offsets {
+            data.extend((header_len + offset).to_be_bytes());
+        }
+
+        // Table offsets are relative to the start of the file, so shift them
+        // past the header.
+        let mut font = font.to_vec();
+        let tables = u16::from_be_bytes([font[4], font[5]]) as usize;
+        for table in 0..tables {
+            let at = 12 + 16 * table + 8;
+            let offset = u32::from_be_bytes(font[at..at + 4].try_into().unwrap());
+            font[at..at + 4].copy_from_slice(&(offset + header_len).to_be_bytes());
+        }
+        data.extend(font);
+        data
+    }
+
+    /// Every face of a collection is loaded; broken faces are skipped.
+    #[test]
+    fn load_font_faces_reads_collections() {
+        crate::logging::init_for_tests();
+        let font = std::fs::read("./assets/Bagnard.otf").unwrap();
+
+        let single = load_font_faces(Bytes::new(font.clone()), Path::new("Bagnard.otf"));
+        assert_eq!(single.len(), 1);
+
+        let collection = font_collection(&font, &[0, 0, font.len() as u32]);
+        let faces = load_font_faces(Bytes::new(collection), Path::new("Bagnard.ttc"));
+        assert_eq!(faces.len(), 2);
+        assert_eq!(faces[1].index(), 1);
+        assert!(faces.iter().all(|face| face.info().family == "Bagnard"));
+    }
 }
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, path::Path, time::SystemTime};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use typst::{
+    foundations::Bytes,
+    syntax::{FileId, Source, VirtualRoot},
+};
+
+use crate::{
+    assets::file_id_from_path,
+    error::{AppError, AppResult},
+};
+
+/// Extension of the sidecar describing a template, e.g. `invoice.meta.json`.
+pub const TEMPLATE_META_EXTENSION: &str = "meta.json";
+/// Extension of the sidecar holding the JSON Schema of a template's input,
+/// e.g. `invoice.schema.json`.
+pub const TEMPLATE_SCHEMA_EXTENSION: &str = "schema.json";
+
+/// Everything the service knows about one template.
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplateInfo {
+    /// File name the template is rendered by, e.g. `invoice.typ`.
+    pub name: String,
+    /// Full virtual path inside the assets directory, e.g. `/invoices/invoice.typ`.
+    pub path: String,
+    /// Size of the source in bytes.
+    pub size: usize,
+    /// Last modification time of the source file; unknown for in-memory contexts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+    /// SHA-256 of the source as `sha256:<hex>`.
+    pub hash: String,
+    /// Human-readable description from the `.meta.json` sidecar.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// JSON Schema of the expected input from the `.schema.json` sidecar.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_schema: Option<serde_json::Value>,
+    /// Example input from the `.meta.json` sidecar.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_input: Option<serde_json::Value>,
+}
+
+/// Contents of a template's `.meta.json` sidecar.
+#[derive(Debug, Default, serde::Deserialize)]
+struct TemplateMetadata {
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    sample_input: Option<serde_json::Value>,
+}
+
+/// Describe every project template, sorted by path.
+pub(crate) fn load_catalog(
+    sources: &[Source],
+    assets: &HashMap<FileId, Bytes>,
+    modified: &HashMap<FileId, SystemTime>,
+) -> AppResult<Vec<TemplateInfo>> {
+    let mut catalog = Vec::new();
+    for source in sources {
+        let id = source.id();
+        if !matches!(id.root(), VirtualRoot::Project) {
+            continue;
+        }
+        let vpath = id.vpath();
+        let Some(name) = vpath.file_name() else {
+            continue;
+        };
+
+        let metadata: TemplateMetadata =
+            read_sidecar(vpath.get_without_slash(), TEMPLATE_META_EXTENSION, assets)?
+                .unwrap_or_default();
+        let input_schema =
+            read_sidecar(vpath.get_without_slash(), TEMPLATE_SCHEMA_EXTENSION, assets)?;
+
+        let text = source.text();
+        catalog.push(TemplateInfo {
+            name: name.to_owned(),
+            path: vpath.get_with_slash().to_owned(),
+            size: text.len(),
+            modified: modified.get(&id).copied().map(DateTime::from),
+            hash: hash(text.as_bytes()),
+            description: metadata.description,
+            input_schema,
+            sample_input: metadata.sample_input,
+        });
+    }
+    catalog.sort_by(|a, b| a.path.cmp(&b.path));
+    debug!(templates = catalog.len(), "Built template catalog");
+    Ok(catalog)
+}
+
+/// Parse the sidecar with `extension` next to the source at `path`, if there is one.
+pub(crate) fn read_sidecar<T: serde::de::DeserializeOwned>(
+    path: &str,
+    extension: &str,
+    assets: &HashMap<FileId, Bytes>,
+) -> AppResult<Option<T>> {
+    let sidecar = Path::new(path).with_extension(extension);
+    let Some(bytes) = assets.get(&file_id_from_path(&sidecar)?) else {
+        return Ok(None);
+    };
+    serde_json::from_slice(bytes.as_slice())
+        .map(Some)
+        .map_err(|source| AppError::TemplateMetadata {
+            file: sidecar.display().to_string(),
+            source,
+        })
+}
+
+/// Content hash of a template source.
+fn hash(bytes: &[u8]) -> String {
+    let hex = Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("sha256:{hex}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash;
+
+    /// Hashes match the SHA-256 of the empty string.
+    #[test]
+    fn hash_matches_reference_vector() {
+        assert_eq!(
+            hash(b""),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use super::{AddrOverride, parse_addr_arg, parse_cli_args_from};
+use super::{AddrOverride, parse_addr_arg, parse_cli_args_from, parse_ready_input};
 
 #[test]
 fn parse_addr_arg_accepts_port_only() {
@@ -56,3 +56,23 @@ fn parse_cli_args_collects_extra_args() {
     assert_eq!(cli.assets_dir.as_deref(), Some("./assets"));
     assert_eq!(cli.extra, vec!["extra".to_string(), "more".to_string()]);
 }
+
+#[test]
+fn parse_cli_args_detects_watch_flag() {
+    let cli = parse_cli_args_from(vec!["--watch", "./assets"]);
+    assert!(cli.watch);
+    assert_eq!(cli.assets_dir.as_deref(), Some("./assets"));
+
+    let cli = parse_cli_args_from(vec!["./assets"]);
+    assert!(!cli.watch);
+}
+
+#[test]
+fn parse_ready_input_rejects_invalid_json() {
+    assert_eq!(parse_ready_input(None).unwrap(), serde_json::json!({}));
+    assert_eq!(
+        parse_ready_input(Some(r#"{"name":"Ready"}"#)).unwrap(),
+        serde_json::json!({ "name": "Ready" })
+    );
+    assert!(parse_ready_input(Some("{name: Ready}")).is_err());
+}
@@ -0,0 +1,113 @@
+use serde::Serialize;
+use typst::{
+    World, WorldExt,
+    diag::{Severity, SourceDiagnostic},
+    syntax::{DiagSpan, Lines, VirtualRoot},
+};
+
+/// Whether a diagnostic aborted the render or was merely a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+}
+
+/// A position inside a template or asset file; line and column are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiagnosticLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A hint or trace entry attached to a [`Diagnostic`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiagnosticNote {
+    pub message: String,
+    pub location: Option<DiagnosticLocation>,
+}
+
+/// A Typst diagnostic with its span resolved against the render's sources.
+///
+/// Spans only carry file ids and node numbers, so they have to be resolved
+/// while the [`World`] that produced them is still around.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+    pub location: Option<DiagnosticLocation>,
+    pub hints: Vec<DiagnosticNote>,
+    pub trace: Vec<DiagnosticNote>,
+}
+
+impl Diagnostic {
+    /// Resolve a raw Typst diagnostic against the world it was produced in.
+    pub(crate) fn resolve(world: &dyn World, diagnostic: &SourceDiagnostic) -> Self {
+        Diagnostic {
+            severity: match diagnostic.severity {
+                Severity::Error => DiagnosticSeverity::Error,
+                Severity::Warning => DiagnosticSeverity::Warning,
+            },
+            message: diagnostic.message.to_string(),
+            location: resolve_location(world, diagnostic.span),
+            hints: diagnostic
+                .hints
+                .iter()
+                .map(|hint| DiagnosticNote {
+                    message: hint.v.to_string(),
+                    location: resolve_location(world, hint.span),
+                })
+                .collect(),
+            trace: diagnostic
+                .trace
+                .iter()
+                .map(|point| DiagnosticNote {
+                    message: point.v.to_string(),
+                    location: resolve_location(world, point.span),
+                })
+                .collect(),
+        }
+    }
+
+    /// Resolve every diagnostic in `diagnostics`, preserving their order.
+    pub(crate) fn resolve_all<'a>(
+        world: &dyn World,
+        diagnostics: impl IntoIterator<Item = &'a SourceDiagnostic>,
+    ) -> Vec<Self> {
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| Self::resolve(world, diagnostic))
+            .collect()
+    }
+}
+
+/// Map a span to a file path and 1-based line/column, if it points into a file.
+fn resolve_location(world: &dyn World, span: impl Into<DiagSpan>) -> Option<DiagnosticLocation> {
+    let span = span.into();
+    let id = span.id()?;
+    let start = world.range(span)?.start;
+
+    // Typst sources carry their own line index; other files (e.g. a JSON
+    // file that failed to parse) are indexed on demand.
+    let (line, column) = match world.source(id) {
+        Ok(source) => source.lines().byte_to_line_column(start)?,
+        Err(_) => {
+            let bytes = world.file(id).ok()?;
+            let text = std::str::from_utf8(&bytes).ok()?;
+            Lines::new(text.to_string()).byte_to_line_column(start)?
+        }
+    };
+
+    let path = id.vpath().get_without_slash();
+    let file = match id.root() {
+        VirtualRoot::Package(spec) => format!("{spec}/{path}"),
+        VirtualRoot::Project => path.to_string(),
+    };
+
+    Some(DiagnosticLocation {
+        file,
+        line: line + 1,
+        column: column + 1,
+    })
+}
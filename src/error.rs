@@ -1,7 +1,8 @@
-use std::io;
+use std::{io, time::Duration};
 use thiserror::Error;
 use tokio::task::JoinError;
-use typst::diag::SourceDiagnostic;
+
+use crate::{diagnostics::Diagnostic, schema::SchemaViolation};
 
 pub type AppResult<T> = Result<T, AppError>;
 
@@ -19,22 +20,151 @@ pub enum AppError {
     NotADirectory(String),
     #[error("failed to serialize input JSON: {0}")]
     InputSerialization(#[from] serde_json::Error),
+    /// A template's `.options.json` sidecar could not be parsed.
+    #[error("invalid template options in `{file}`: {source}")]
+    TemplateOptions {
+        file: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// A template's `.meta.json` or `.schema.json` sidecar could not be parsed.
+    #[error("invalid template metadata in `{file}`: {source}")]
+    TemplateMetadata {
+        file: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// A template's `.schema.json` sidecar is not a valid JSON Schema.
+    #[error("invalid input schema in `{file}`: {message}")]
+    TemplateSchema { file: String, message: String },
+    #[error("input does not match the template schema: {}", format_violations(.0))]
+    InputValidation(Vec<SchemaViolation>),
     #[error("main source `{0}` not found")]
     MainSourceNotFound(String),
     #[error("Typst compilation failed: {0:#?}")]
-    TypstCompilation(Vec<SourceDiagnostic>),
+    TypstCompilation(Vec<Diagnostic>),
     #[error("PDF export failed: {0:#?}")]
-    PdfExport(Vec<SourceDiagnostic>),
+    PdfExport(Vec<Diagnostic>),
+    #[error("PNG export failed: {0}")]
+    PngExport(String),
+    #[error("invalid render options: {0}")]
+    InvalidRenderOptions(String),
+    #[error("page {page} out of range, document has {page_count} pages")]
+    PageOutOfRange { page: usize, page_count: usize },
+    /// The render ran longer than its time limit and was aborted.
+    #[error("render exceeded its time limit of {0:?}")]
+    RenderTimeout(Duration),
+    /// The render was cancelled before it finished, e.g. by a disconnect.
+    #[error("render was cancelled")]
+    RenderCancelled,
     #[error("Background task failed to complete: {0}")]
     TaskJoin(#[from] JoinError),
     /// The client closed the connection before the ZIP archive was fully written.
     #[error("Client closed connection before ZIP archive completed")]
     ConnectionClosed,
+    /// Rendered PDFs could not be combined into one document.
+    #[error("PDF merge error: {0}")]
+    PdfMerge(#[from] lopdf::Error),
     /// An error bubbled up from the underlying ZIP writer.
     #[error("ZIP writer error: {0}")]
     ZipError(#[from] async_zip::error::ZipError),
+    /// No job with this id exists, or it has already expired.
+    #[error("job `{0}` not found")]
+    JobNotFound(uuid::Uuid),
+    /// The job is still running or failed, so there is no archive to download.
+    #[error("job `{0}` has no result to download")]
+    JobResultUnavailable(uuid::Uuid),
+    /// The context was built in memory, so there is no directory to reload from.
+    #[error("context was not loaded from a directory and cannot be reloaded")]
+    ReloadUnavailable,
+    /// Another reload of the context is still running.
+    #[error("a reload is already in progress")]
+    ReloadInProgress,
+    /// The assets directory could not be watched for changes.
+    #[cfg(feature = "hot-reload")]
+    #[error("file watcher error: {0}")]
+    Watch(#[from] notify::Error),
 }
 
+/// Join schema violations into a single line for the error message.
+fn format_violations(violations: &[SchemaViolation]) -> String {
+    violations
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl AppError {
+    /// A stable snake_case name of the variant, e.g. for metric labels.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::Io(_) => "io",
+            AppError::CanonicalizePath { .. } => "canonicalize_path",
+            AppError::NotADirectory(_) => "not_a_directory",
+            AppError::InputSerialization(_) => "input_serialization",
+            AppError::TemplateOptions { .. } => "template_options",
+            AppError::TemplateMetadata { .. } => "template_metadata",
+            AppError::TemplateSchema { .. } => "template_schema",
+            AppError::InputValidation(_) => "input_validation",
+            AppError::MainSourceNotFound(_) => "main_source_not_found",
+            AppError::TypstCompilation(_) => "typst_compilation",
+            AppError::PdfExport(_) => "pdf_export",
+            AppError::PngExport(_) => "png_export",
+            AppError::InvalidRenderOptions(_) => "invalid_render_options",
+            AppError::PageOutOfRange { .. } => "page_out_of_range",
+            AppError::RenderTimeout(_) => "render_timeout",
+            AppError::RenderCancelled => "render_cancelled",
+            AppError::TaskJoin(_) => "task_join",
+            AppError::ConnectionClosed => "connection_closed",
+            AppError::PdfMerge(_) => "pdf_merge",
+            AppError::ZipError(_) => "zip_error",
+            AppError::JobNotFound(_) => "job_not_found",
+            AppError::JobResultUnavailable(_) => "job_result_unavailable",
+            AppError::ReloadUnavailable => "reload_unavailable",
+            AppError::ReloadInProgress => "reload_in_progress",
+            #[cfg(feature = "hot-reload")]
+            AppError::Watch(_) => "watch",
+        }
+    }
+
+    /// A short description that is safe to show to any client, without
+    /// paths or other internals.
+    pub(crate) fn public_message(&self) -> &'static str {
+        match self {
+            AppError::Io(_) => "I/O operation failed",
+            AppError::CanonicalizePath { .. } => "Failed to resolve file path",
+            AppError::NotADirectory(_) => "Provided path is not a directory",
+            AppError::InputSerialization(_) => "Invalid request payload",
+            AppError::TemplateOptions { .. } => "Invalid template options",
+            AppError::TemplateMetadata { .. } => "Invalid template metadata",
+            AppError::TemplateSchema { .. } => "Invalid template input schema",
+            AppError::InputValidation(_) => "Input does not match the template schema",
+            AppError::MainSourceNotFound(_) => "Requested template not found",
+            AppError::TypstCompilation(_) => "Document compilation failed",
+            AppError::PdfExport(_) => "PDF export failed",
+            AppError::PngExport(_) => "PNG export failed",
+            AppError::InvalidRenderOptions(_) => "Invalid render options",
+            AppError::PageOutOfRange { .. } => "Requested page does not exist",
+            AppError::TaskJoin(_) => "Worker task failed to complete",
+            AppError::ConnectionClosed => "Client closed connection",
+            AppError::ZipError(_) => "Failed to stream ZIP archive",
+            AppError::PdfMerge(_) => "Failed to merge PDFs",
+            AppError::JobNotFound(_) => "Job not found",
+            AppError::JobResultUnavailable(_) => "Job result is not available",
+            AppError::ReloadUnavailable => "Context cannot be reloaded",
+            AppError::ReloadInProgress => "Reload already in progress",
+            AppError::RenderTimeout(_) => "Render exceeded its time limit",
+            AppError::RenderCancelled => "Render was cancelled",
+            #[cfg(feature = "hot-reload")]
+            AppError::Watch(_) => "Failed to watch assets directory",
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+pub(crate) use response::DetailedErrorBody;
+
 #[cfg(feature = "server")]
 mod response {
     use axum::{
@@ -48,34 +178,49 @@ mod response {
 
     use super::AppError;
 
+    /// The error body extended with resolved Typst diagnostics.
+    ///
+    /// Attached to error responses as an extension; the server only swaps it
+    /// in for the public body when diagnostics are exposed for the request.
+    #[derive(Debug, Clone)]
+    pub(crate) struct DetailedErrorBody(pub(crate) serde_json::Value);
+
+    /// Status for a request whose client went away before the response.
+    const CLIENT_CLOSED_REQUEST: StatusCode = match StatusCode::from_u16(499) {
+        Ok(status) => status,
+        Err(_) => unreachable!(),
+    };
+
     impl AppError {
         fn status_code(&self) -> StatusCode {
             match self {
-                AppError::MainSourceNotFound(_) => StatusCode::NOT_FOUND,
+                AppError::MainSourceNotFound(_) | AppError::JobNotFound(_) => StatusCode::NOT_FOUND,
+                AppError::ReloadUnavailable
+                | AppError::ReloadInProgress
+                | AppError::JobResultUnavailable(_) => StatusCode::CONFLICT,
+                AppError::InputValidation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                AppError::RenderTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+                // nginx's "client closed request"; the client is gone, so
+                // this only shows up in logs and metrics.
+                AppError::RenderCancelled => CLIENT_CLOSED_REQUEST,
                 AppError::TypstCompilation(_)
                 | AppError::CanonicalizePath { .. }
                 | AppError::NotADirectory(_)
                 | AppError::ConnectionClosed
-                | AppError::InputSerialization(_) => StatusCode::BAD_REQUEST,
+                | AppError::InputSerialization(_)
+                | AppError::InvalidRenderOptions(_)
+                | AppError::PageOutOfRange { .. } => StatusCode::BAD_REQUEST,
                 AppError::Io(_)
                 | AppError::PdfExport(_)
+                | AppError::PngExport(_)
+                | AppError::TemplateOptions { .. }
+                | AppError::TemplateMetadata { .. }
+                | AppError::TemplateSchema { .. }
+                | AppError::PdfMerge(_)
                 | AppError::TaskJoin(_)
                 | AppError::ZipError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            }
-        }
-
-        fn public_message(&self) -> &'static str {
-            match self {
-                AppError::Io(_) => "I/O operation failed",
-                AppError::CanonicalizePath { .. } => "Failed to resolve file path",
-                AppError::NotADirectory(_) => "Provided path is not a directory",
-                AppError::InputSerialization(_) => "Invalid request payload",
-                AppError::MainSourceNotFound(_) => "Requested template not found",
-                AppError::TypstCompilation(_) => "Document compilation failed",
-                AppError::PdfExport(_) => "PDF export failed",
-                AppError::TaskJoin(_) => "Worker task failed to complete",
-                AppError::ConnectionClosed => "Client closed connection",
-                AppError::ZipError(_) => "Failed to stream ZIP archive",
+                #[cfg(feature = "hot-reload")]
+                AppError::Watch(_) => StatusCode::INTERNAL_SERVER_ERROR,
             }
         }
     }
@@ -86,19 +231,47 @@ mod response {
             let reference = Uuid::new_v4();
 
             error!(%reference, error = ?self, "Application error encountered");
+            metrics::counter!("tws_http_errors_total", "error" => self.kind()).increment(1);
+
+            let mut body = json!({
+                "error": self.public_message(),
+                "reference": reference.to_string(),
+            });
+
+            // Violations describe the caller's own input, so they are always included.
+            if let AppError::InputValidation(violations) = &self {
+                body["violations"] = json!(violations);
+            }
 
             if let AppError::PdfExport(diagnostics) | AppError::TypstCompilation(diagnostics) =
                 &self
             {
                 for diag in diagnostics {
-                    eprintln!("Typst compilation error {reference}: {}", diag.message);
+                    match &diag.location {
+                        Some(location) => error!(
+                            %reference,
+                            file = %location.file,
+                            line = location.line,
+                            column = location.column,
+                            "Typst diagnostic: {}",
+                            diag.message
+                        ),
+                        None => error!(%reference, "Typst diagnostic: {}", diag.message),
+                    }
                 }
+
+                let detailed = json!({
+                    "error": self.public_message(),
+                    "reference": reference.to_string(),
+                    "diagnostics": diagnostics,
+                });
+                let mut response = (status, Json(body)).into_response();
+                response
+                    .extensions_mut()
+                    .insert(DetailedErrorBody(detailed));
+                return response;
             }
 
-            let body = json!({
-                "error": self.public_message(),
-                "reference": reference.to_string(),
-            });
             (status, Json(body)).into_response()
         }
     }
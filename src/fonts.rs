@@ -0,0 +1,472 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use serde::Serialize;
+use tracing::{debug, trace, warn};
+use typst::{
+    foundations::Bytes,
+    text::{Font, FontBook, FontInfo, FontStyle},
+};
+use unicode_script::{Script, UnicodeScript as _};
+
+use crate::{
+    assets::is_font_file,
+    error::{AppError, AppResult},
+};
+
+/// Where a font available to templates comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontSource {
+    /// The assets directory, or the files passed to `PdfContext::from_assets`.
+    Project,
+    /// One of the shared font directories in `ContextOptions::font_dirs`.
+    Shared,
+    /// The operating system's font directories.
+    System,
+    /// The fonts compiled in with the `embedded-fonts` feature.
+    Embedded,
+}
+
+/// A font face available to templates.
+#[derive(Debug, Clone, Serialize)]
+pub struct FontDescription {
+    pub family: String,
+    pub style: FontStyle,
+    /// Weight between 100 (thin) and 900 (black); 400 is regular.
+    pub weight: u16,
+    /// Width relative to the normal width, between 0.5 and 2.0.
+    pub stretch: f64,
+    pub source: FontSource,
+    /// File the face comes from, relative to the assets directory or the
+    /// font directory it was found in; absent for embedded fonts. Host paths
+    /// are never exposed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Index of the face within a font collection; `0` for single fonts.
+    pub index: u32,
+    pub coverage: FontCoverage,
+}
+
+/// Which characters a font face has glyphs for.
+#[derive(Debug, Clone, Serialize)]
+pub struct FontCoverage {
+    /// Number of covered Unicode code points.
+    pub codepoints: usize,
+    /// Scripts with at least one covered code point, e.g. `Latin`, sorted
+    /// by name; characters shared between scripts count as `Common`.
+    pub scripts: Vec<String>,
+}
+
+/// A font face that is either already in memory or read from disk the first
+/// time a render asks for it.
+pub(crate) struct FontSlot {
+    source: FontSource,
+    /// File the face comes from; lazy slots read it from there.
+    path: Option<PathBuf>,
+    /// `path` relative to the directory it was found in, for descriptions.
+    file: Option<String>,
+    index: u32,
+    font: OnceLock<Option<Font>>,
+}
+
+impl FontSlot {
+    fn loaded(source: FontSource, path: Option<PathBuf>, font: Font) -> Self {
+        Self {
+            source,
+            file: path.as_ref().map(|path| path.display().to_string()),
+            path,
+            index: font.index(),
+            font: OnceLock::from(Some(font)),
+        }
+    }
+
+    /// A face of the font file at `path`, found below `dir`.
+    fn lazy(source: FontSource, dir: &Path, path: PathBuf, index: u32) -> Self {
+        let file = match path.strip_prefix(dir) {
+            Ok(relative) => relative.display().to_string(),
+            Err(_) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        Self {
+            source,
+            file: Some(file),
+            path: Some(path),
+            index,
+            font: OnceLock::new(),
+        }
+    }
+
+    /// The font, reading it from disk on first use.
+    pub(crate) fn get(&self) -> Option<Font> {
+        self.font
+            .get_or_init(|| {
+                let path = self.path.as_ref()?;
+                let index = self.index;
+                let data = fs::read(path)
+                    .map_err(|error| warn!(path = %path.display(), %error, "Failed to read font"))
+                    .ok()?;
+                let font = Font::new(Bytes::new(data), index);
+                match &font {
+                    Some(_) => debug!(path = %path.display(), index, "Loaded font on first use"),
+                    None => warn!(path = %path.display(), index, "Font face no longer parses"),
+                }
+                font
+            })
+            .clone()
+    }
+
+    /// Describe the face from its font book entry, without loading it.
+    pub(crate) fn describe(&self, info: &FontInfo) -> FontDescription {
+        FontDescription {
+            family: info.family.clone(),
+            style: info.variant.style,
+            weight: info.variant.weight.to_number(),
+            stretch: info.variant.stretch.to_ratio().get(),
+            source: self.source,
+            file: self.file.clone(),
+            index: self.index,
+            coverage: coverage(info),
+        }
+    }
+}
+
+/// Summarize the code points covered by a font face.
+fn coverage(info: &FontInfo) -> FontCoverage {
+    let mut codepoints = 0;
+    let mut scripts = BTreeSet::new();
+    for codepoint in info.coverage.iter() {
+        codepoints += 1;
+        if let Some(c) = char::from_u32(codepoint) {
+            let script = c.script();
+            if script != Script::Unknown {
+                scripts.insert(script.full_name());
+            }
+        }
+    }
+
+    FontCoverage {
+        codepoints,
+        scripts: scripts.into_iter().map(str::to_owned).collect(),
+    }
+}
+
+/// The font book of a context together with the faces it describes.
+///
+/// Fonts come in layers: project fonts first, then each shared directory in
+/// the configured order, then the OS font directories. A family provided by
+/// one layer hides the faces of that family in every later layer, so a
+/// project can override a shared font by shipping its own copy.
+#[derive(Default)]
+pub(crate) struct Fonts {
+    pub(crate) book: FontBook,
+    pub(crate) slots: Vec<FontSlot>,
+    /// Lowercased families of the layers added so far.
+    families: HashSet<String>,
+}
+
+impl Fonts {
+    /// Start with the project's fonts, already in memory, and the files
+    /// they were read from.
+    pub(crate) fn new(project: Vec<(PathBuf, Font)>) -> Self {
+        let mut fonts = Self::default();
+        for (_, font) in &project {
+            fonts.book.push(font.info().clone());
+        }
+        fonts.families = families(project.iter().map(|(_, font)| font.info()));
+        fonts.slots = project
+            .into_iter()
+            .map(|(path, font)| FontSlot::loaded(FontSource::Project, Some(path), font))
+            .collect();
+        fonts
+    }
+
+    /// Add the faces found below `dir` as a new layer; only their metadata is
+    /// read now.
+    pub(crate) fn add_dir(&mut self, dir: &Path, source: FontSource) -> AppResult<()> {
+        if !dir.is_dir() {
+            return Err(AppError::NotADirectory(dir.display().to_string()));
+        }
+
+        let mut faces = Vec::new();
+        scan_dir(dir, &mut faces, &mut HashSet::new());
+        let layer = families(faces.iter().map(|(_, _, info)| info));
+
+        let mut added = 0;
+        for (path, index, info) in faces {
+            if self.families.contains(&info.family.to_lowercase()) {
+                trace!(
+                    path = %path.display(),
+                    family = %info.family,
+                    "Font family is already provided; skipping"
+                );
+                continue;
+            }
+            self.book.push(info);
+            self.slots.push(FontSlot::lazy(source, dir, path, index));
+            added += 1;
+        }
+        self.families.extend(layer);
+        debug!(path = %dir.display(), faces = added, "Indexed shared fonts");
+        Ok(())
+    }
+
+    /// Add the fonts compiled into the binary as the last layer, so text
+    /// still renders when no other font covers it. Without the
+    /// `embedded-fonts` feature there are none and this does nothing.
+    pub(crate) fn add_embedded(&mut self) {
+        #[cfg(feature = "embedded-fonts")]
+        {
+            let fonts = typst_assets::fonts()
+                .flat_map(|data| Font::iter(Bytes::new(data)))
+                .filter(|font| !self.families.contains(&font.info().family.to_lowercase()))
+                .collect::<Vec<_>>();
+            debug!(faces = fonts.len(), "Added embedded fonts");
+
+            let layer = families(fonts.iter().map(Font::info));
+            for font in fonts {
+                self.book.push(font.info().clone());
+                self.slots
+                    .push(FontSlot::loaded(FontSource::Embedded, None, font));
+            }
+            self.families.extend(layer);
+        }
+    }
+
+    /// Add the OS font directories that exist, in [`system_font_dirs`] order.
+    pub(crate) fn add_system(&mut self) {
+        for dir in system_font_dirs() {
+            if dir.is_dir()
+                && let Err(error) = self.add_dir(&dir, FontSource::System)
+            {
+                warn!(path = %dir.display(), %error, "Failed to index system fonts");
+            }
+        }
+    }
+}
+
+/// Lowercased family names of `infos`.
+fn families<'a>(infos: impl Iterator<Item = &'a FontInfo>) -> HashSet<String> {
+    infos.map(|info| info.family.to_lowercase()).collect()
+}
+
+/// Metadata of the faces of a scanned font file, with the size and
+/// modification time it had when it was read.
+struct ScannedFont {
+    len: u64,
+    modified: SystemTime,
+    faces: Vec<(u32, FontInfo)>,
+}
+
+/// Scanned font files by path, shared across contexts so that reloads only
+/// read fonts that changed since.
+static SCANNED: LazyLock<Mutex<HashMap<PathBuf, ScannedFont>>> = LazyLock::new(Default::default);
+
+fn scanned() -> std::sync::MutexGuard<'static, HashMap<PathBuf, ScannedFont>> {
+    SCANNED.lock().unwrap_or_else(|poison| poison.into_inner())
+}
+
+/// Recursively collect the metadata of every font face below `dir`.
+///
+/// Directories already in `visited` are skipped, so symlink cycles end.
+fn scan_dir(dir: &Path, faces: &mut Vec<(PathBuf, u32, FontInfo)>, visited: &mut HashSet<PathBuf>) {
+    let canonical = match fs::canonicalize(dir) {
+        Ok(canonical) => canonical,
+        Err(error) => {
+            warn!(path = %dir.display(), %error, "Failed to resolve font directory");
+            return;
+        }
+    };
+    if !visited.insert(canonical) {
+        trace!(path = %dir.display(), "Font directory was already scanned; skipping");
+        return;
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            warn!(path = %dir.display(), %error, "Failed to read font directory");
+            return;
+        }
+    };
+
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<_>>();
+    // Sort for a stable font book regardless of the file system's order.
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            scan_dir(&path, faces, visited);
+        } else if is_font_file(&path) {
+            faces.extend(
+                scan_file(&path)
+                    .into_iter()
+                    .map(|(index, info)| (path.clone(), index, info)),
+            );
+        }
+    }
+}
+
+/// The metadata of every face in the font file at `path`, reading the file
+/// only if it changed since it was last scanned.
+fn scan_file(path: &Path) -> Vec<(u32, FontInfo)> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            warn!(path = %path.display(), %error, "Failed to read font");
+            return Vec::new();
+        }
+    };
+    let len = metadata.len();
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    if let Some(scanned) = scanned().get(path)
+        && scanned.len == len
+        && scanned.modified == modified
+    {
+        trace!(path = %path.display(), "Font is unchanged since the last scan");
+        return scanned.faces.clone();
+    }
+
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(error) => {
+            warn!(path = %path.display(), %error, "Failed to read font");
+            return Vec::new();
+        }
+    };
+    let count = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+    let faces = (0..count)
+        .filter_map(|index| match FontInfo::new(&data, index) {
+            Some(info) => Some((index, info)),
+            None => {
+                warn!(path = %path.display(), index, "Skipping font face that failed to parse");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    scanned().insert(
+        path.to_path_buf(),
+        ScannedFont {
+            len,
+            modified,
+            faces: faces.clone(),
+        },
+    );
+    faces
+}
+
+/// Directories the operating system keeps fonts in.
+fn system_font_dirs() -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let mut dirs = Vec::new();
+
+    if cfg!(target_os = "macos") {
+        dirs.extend(home.map(|home| home.join("Library/Fonts")));
+        dirs.push("/Library/Fonts".into());
+        dirs.push("/System/Library/Fonts".into());
+    } else if cfg!(windows) {
+        if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+            dirs.push(PathBuf::from(local).join("Microsoft\\Windows\\Fonts"));
+        }
+        let windir = std::env::var_os("WINDIR").unwrap_or_else(|| "C:\\Windows".into());
+        dirs.push(PathBuf::from(windir).join("Fonts"));
+    } else {
+        if let Some(home) = home {
+            dirs.push(home.join(".local/share/fonts"));
+            dirs.push(home.join(".fonts"));
+        }
+        dirs.push("/usr/local/share/fonts".into());
+        dirs.push("/usr/share/fonts".into());
+    }
+
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FontSource, Fonts};
+    use typst::{foundations::Bytes, text::Font};
+
+    /// Shared fonts are only read on first use, and project fonts win over
+    /// shared fonts of the same family.
+    #[test]
+    fn shared_fonts_load_lazily_behind_project_fonts() {
+        crate::logging::init_for_tests();
+        let shared = std::env::temp_dir().join(format!("tws-fonts-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(shared.join("nested")).unwrap();
+        std::fs::copy("./assets/Bagnard.otf", shared.join("nested/Bagnard.otf")).unwrap();
+
+        let mut fonts = Fonts::default();
+        fonts.add_dir(&shared, FontSource::Shared).unwrap();
+        assert_eq!(fonts.slots.len(), 1);
+        assert!(fonts.slots[0].font.get().is_none(), "expected a lazy slot");
+        let font = fonts.slots[0].get().expect("shared font should load");
+        assert_eq!(font.info().family, "Bagnard");
+        // Listings name the file relative to its font directory.
+        let description = fonts.slots[0].describe(&font.info().clone());
+        assert_eq!(
+            description.file.as_deref(),
+            Some(
+                std::path::Path::new("nested/Bagnard.otf")
+                    .display()
+                    .to_string()
+                    .as_str()
+            )
+        );
+
+        let data = std::fs::read("./assets/Bagnard.otf").unwrap();
+        let project = Font::new(Bytes::new(data), 0).unwrap();
+        let mut fonts = Fonts::new(vec![("Bagnard.otf".into(), project)]);
+        fonts.add_dir(&shared, FontSource::Shared).unwrap();
+        assert_eq!(
+            fonts.slots.len(),
+            1,
+            "project font should shadow the shared one"
+        );
+        assert_eq!(fonts.slots[0].source, FontSource::Project);
+
+        let missing = fonts.add_dir(&shared.join("missing"), FontSource::Shared);
+        assert!(missing.is_err());
+        std::fs::remove_dir_all(&shared).unwrap();
+    }
+
+    /// Symlink cycles end the scan instead of recursing forever, and scanned
+    /// files are remembered for the next scan.
+    #[cfg(unix)]
+    #[test]
+    fn scan_survives_symlink_cycles() {
+        crate::logging::init_for_tests();
+        let shared = std::env::temp_dir().join(format!("tws-fonts-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(shared.join("nested")).unwrap();
+        std::fs::copy("./assets/Bagnard.otf", shared.join("nested/Bagnard.otf")).unwrap();
+        std::os::unix::fs::symlink(&shared, shared.join("nested/loop")).unwrap();
+
+        let mut fonts = Fonts::default();
+        fonts.add_dir(&shared, FontSource::Shared).unwrap();
+        assert_eq!(fonts.slots.len(), 1);
+        assert!(super::scanned().contains_key(&shared.join("nested/Bagnard.otf")));
+        std::fs::remove_dir_all(&shared).unwrap();
+    }
+
+    /// Embedded fonts only fill in families that no earlier layer provides.
+    #[cfg(feature = "embedded-fonts")]
+    #[test]
+    fn embedded_fonts_fill_in_missing_families() {
+        let mut fonts = Fonts::default();
+        fonts.add_embedded();
+        assert!(fonts.book.contains_family("libertinus serif"));
+
+        let faces = fonts.slots.len();
+        fonts.add_embedded();
+        assert_eq!(fonts.slots.len(), faces);
+    }
+}
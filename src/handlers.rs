@@ -1,26 +1,220 @@
-use std::sync::Arc;
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
 
 use axum::{
     Json,
     body::Body,
-    extract::{Path, State},
-    http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE},
-    response::IntoResponse,
+    extract::{FromRef, Path, Query, Request, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, LOCATION},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
 };
 use axum_extra::response::Attachment;
-use tracing::{debug, info, instrument};
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+use tracing::{debug, info, instrument, warn};
+use uuid::Uuid;
 
 use crate::{
-    CRATE_INFO,
-    error::AppError,
-    pdf::{BatchRenderRequest, PdfContext},
+    CRATE_INFO, DiagnosticsMode, ReadinessCheck, ServerOptions,
+    error::{AppError, AppResult, DetailedErrorBody},
+    jobs::{JobStatus, JobStore},
+    pdf::{
+        BatchErrorMode, BatchOptions, BatchOrder, BatchRenderRequest, PageSelection, PdfContext,
+        PdfOptions, PdfTimestamp, PngOptions, RenderOptions, RenderWarnings, SvgOptions,
+        failure_message,
+    },
+    reload::ContextHandle,
+    telemetry::{self, RenderTimeouts, track_render},
+    zip::ZipResponseWriter,
 };
 
 const BATCH_ARCHIVE_NAME: &str = "rendered-pdfs.zip";
+const BATCH_MERGED_NAME: &str = "rendered-pdfs.pdf";
+
+/// Request header lowering the server's render time limit, in milliseconds.
+const RENDER_TIMEOUT_HEADER: &str = "x-render-timeout-ms";
+
+/// Request header pinning the render's current time (RFC 3339).
+const RENDER_NOW_HEADER: &str = "x-render-now";
+
+/// Request header carrying a JSON object of strings exposed to the template
+/// as `sys.inputs`.
+const RENDER_INPUTS_HEADER: &str = "x-typst-inputs";
+
+/// Response header listing the font families a render asked for but no font
+/// provides, comma-separated.
+const UNKNOWN_FONTS_HEADER: &str = "x-typst-unknown-fonts";
+
+/// State shared by all handlers.
+#[derive(Clone)]
+pub(crate) struct AppState {
+    pub(crate) context: ContextHandle,
+    pub(crate) jobs: JobStore,
+    pub(crate) options: Arc<ServerOptions>,
+    /// Outcome of the last readiness smoke render.
+    pub(crate) smoke_render: Arc<Mutex<SmokeOutcome>>,
+    /// Renders `/metrics`; `None` if another metrics recorder is installed.
+    pub(crate) metrics: Option<PrometheusHandle>,
+}
+
+impl FromRef<AppState> for ContextHandle {
+    fn from_ref(state: &AppState) -> Self {
+        state.context.clone()
+    }
+}
+
+impl AppState {
+    /// The time limit for a render that asked for `requested`: the server's
+    /// limit, if any, caps what clients can ask for.
+    fn render_timeout(&self, requested: Option<Duration>) -> Option<Duration> {
+        match (requested, self.options.render_timeout) {
+            (Some(requested), Some(limit)) => Some(requested.min(limit)),
+            (requested, limit) => requested.or(limit),
+        }
+    }
+
+    /// Build the render options for a single request from the server
+    /// defaults and the request's override header.
+    fn render_options(&self, headers: &HeaderMap) -> AppResult<RenderOptions> {
+        let timeout = match headers.get(RENDER_TIMEOUT_HEADER) {
+            Some(value) => {
+                let millis = value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+                    .ok_or_else(|| {
+                        AppError::InvalidRenderOptions(format!(
+                            "{RENDER_TIMEOUT_HEADER} must be a number of milliseconds"
+                        ))
+                    })?;
+                Some(Duration::from_millis(millis))
+            }
+            None => None,
+        };
+        let timeout = self.render_timeout(timeout);
+
+        let now = headers
+            .get(RENDER_NOW_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.trim().parse().ok())
+                    .ok_or_else(|| {
+                        AppError::InvalidRenderOptions(format!(
+                            "{RENDER_NOW_HEADER} must be an RFC 3339 timestamp"
+                        ))
+                    })
+            })
+            .transpose()?;
+
+        let inputs = headers
+            .get(RENDER_INPUTS_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| serde_json::from_str(value).ok())
+                    .ok_or_else(|| {
+                        AppError::InvalidRenderOptions(format!(
+                            "{RENDER_INPUTS_HEADER} must be a JSON object of strings"
+                        ))
+                    })
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(RenderOptions {
+            timeout,
+            now,
+            inputs,
+            warnings: Some(RenderWarnings::default()),
+            ..Default::default()
+        })
+    }
+
+    /// Apply the server's batch settings to a batch and build its options.
+    fn batch_options(
+        &self,
+        requests: &mut [BatchRenderRequest],
+        errors: Option<BatchErrorMode>,
+        order: Option<BatchOrder>,
+        headers: &HeaderMap,
+    ) -> BatchOptions {
+        for request in requests {
+            request.options.timeout = self.render_timeout(request.options.timeout);
+        }
+        BatchOptions {
+            errors: errors.unwrap_or_default(),
+            diagnostics: diagnostics_exposed(self.options.diagnostics, headers),
+            order: order.unwrap_or_default(),
+            concurrency: self.options.batch_concurrency,
+            limiter: self.options.render_limiter.clone(),
+            progress: None,
+        }
+    }
+}
+
+/// Run a render on the blocking pool once the server's render limiter
+/// grants a slot.
+///
+/// Waiting (for a slot and for the render) stops as soon as the time limit
+/// passes, and the render is cancelled when it times out or when this future
+/// is dropped because the client disconnected.
+///
+/// The time limit is soft: Typst only stops at its next file access, and
+/// until then the render keeps its limiter slot, so the limiter still bounds
+/// the renders actually running. Such renders are counted as abandoned, and
+/// the timeout is counted as a failure of the render in `timeouts`.
+async fn spawn_render<T, F>(
+    state: &AppState,
+    timeouts: RenderTimeouts,
+    mut options: RenderOptions,
+    render: F,
+) -> AppResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce(RenderOptions) -> AppResult<T> + Send + 'static,
+{
+    let cancellation = CancellationToken::new();
+    options.cancellation = Some(cancellation.clone());
+    options.timeout_start = Some(Instant::now());
+    let _cancel_on_drop = cancellation.drop_guard();
+    let (_caller, worker) = track_render();
+
+    let timeout = options.timeout;
+    let limiter = state.options.render_limiter.clone();
+    let task = async move {
+        let permit = match limiter {
+            Some(limiter) => Some(limiter.acquire().await),
+            None => None,
+        };
+        tokio::task::spawn_blocking(telemetry::blocking(move || {
+            let _worker = worker;
+            let _permit = permit;
+            render(options)
+        }))
+        .await?
+    };
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, task).await.map_err(|_| {
+            let error = AppError::RenderTimeout(timeout);
+            timeouts.timed_out(&error);
+            error
+        })?,
+        None => task.await,
+    }
+}
 
 /// Report the running crate name and version.
-pub(crate) async fn root(State(pdf_context): State<Arc<PdfContext>>) -> String {
-    let templates = pdf_context.template_names();
+pub(crate) async fn root(State(context): State<ContextHandle>) -> String {
+    let templates = context.load().template_names();
 
     if templates.is_empty() {
         return format!("{CRATE_INFO}\n\nTemplates:\n(none)");
@@ -29,41 +223,681 @@ pub(crate) async fn root(State(pdf_context): State<Arc<PdfContext>>) -> String {
     format!("{CRATE_INFO}\n\nTemplates:\n{}", templates.join("\n"))
 }
 
+/// List every template with its metadata as JSON.
+pub(crate) async fn templates(State(context): State<ContextHandle>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "templates": context.load().catalog() }))
+}
+
+/// List every font face available to templates as JSON.
+///
+/// Describing the fonts walks the coverage of every face the first time, so
+/// it runs on the blocking pool.
+pub(crate) async fn fonts(
+    State(context): State<ContextHandle>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let context = context.load();
+    let fonts = tokio::task::spawn_blocking(telemetry::blocking(
+        move || serde_json::json!({ "fonts": context.fonts() }),
+    ))
+    .await?;
+    Ok(Json(fonts))
+}
+
+/// Liveness probe: the process is up and handling requests.
+pub(crate) async fn healthz() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Expose the collected metrics in the Prometheus text format.
+pub(crate) async fn metrics(State(state): State<AppState>) -> Response {
+    let Some(handle) = &state.metrics else {
+        return (StatusCode::NOT_FOUND, "metrics are recorded elsewhere").into_response();
+    };
+    if let Some(limiter) = &state.options.render_limiter {
+        metrics::gauge!("tws_render_slots").set(limiter.limit() as f64);
+        metrics::gauge!("tws_render_slots_available").set(limiter.available() as f64);
+    }
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+        .into_response()
+}
+
+/// Outcome of the smoke render run by the readiness probe.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SmokeRender {
+    /// No [`ReadinessCheck`] is configured.
+    Skipped,
+    Ok,
+    Failed,
+}
+
+/// Body returned by `/readyz`.
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Readiness {
+    status: &'static str,
+    templates: usize,
+    smoke_render: SmokeRender,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Readiness probe: templates are loaded and, if configured, the smoke render
+/// succeeds. Responds with `503 Service Unavailable` otherwise.
+///
+/// The failure is only described in detail when diagnostics are exposed.
+pub(crate) async fn readyz(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<Readiness>) {
+    let context = state.context.load();
+    let templates = context.template_names().len();
+    let (smoke_render, error) = if templates == 0 {
+        (
+            SmokeRender::Skipped,
+            Some("no templates loaded".to_string()),
+        )
+    } else {
+        match &state.options.readiness {
+            None => (SmokeRender::Skipped, None),
+            Some(check) => {
+                let detailed = diagnostics_exposed(state.options.diagnostics, &headers);
+                match smoke_render(&state, check, context, detailed).await {
+                    Ok(()) => (SmokeRender::Ok, None),
+                    Err(message) => (SmokeRender::Failed, Some(message)),
+                }
+            }
+        }
+    };
+
+    let (status, label) = match error {
+        None => (StatusCode::OK, "ready"),
+        Some(_) => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+    };
+    (
+        status,
+        Json(Readiness {
+            status: label,
+            templates,
+            smoke_render,
+            error,
+        }),
+    )
+}
+
+/// How long a failed smoke render is reported before it is retried with the
+/// same context, so a timeout or other passing failure does not stick.
+const SMOKE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The last readiness smoke render and the context it ran with.
+#[derive(Default)]
+pub(crate) struct SmokeOutcome {
+    /// The weak reference keeps the allocation alive, so its address cannot
+    /// be reused by a newer context.
+    context: Weak<PdfContext>,
+    /// When the render failed and why; `None` if it succeeded.
+    failure: Option<(Instant, AppError)>,
+}
+
+impl SmokeOutcome {
+    /// The cached outcome for `context`, unless the render has to run again.
+    fn cached(&self, context: &Arc<PdfContext>, detailed: bool) -> Option<Result<(), String>> {
+        if self.context.as_ptr() != Arc::as_ptr(context) {
+            return None;
+        }
+        match &self.failure {
+            None => Some(Ok(())),
+            Some((failed, error)) if failed.elapsed() < SMOKE_RETRY_INTERVAL => {
+                Some(Err(failure_message(error, detailed)))
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+/// Run the readiness smoke render unless its outcome for `context` is
+/// cached, and describe a failure in detail if `detailed` is set.
+///
+/// Successes are kept until the context is reloaded and failures for
+/// [`SMOKE_RETRY_INTERVAL`], so probes cannot keep the blocking pool busy
+/// with a template that does not render.
+async fn smoke_render(
+    state: &AppState,
+    check: &ReadinessCheck,
+    context: Arc<PdfContext>,
+    detailed: bool,
+) -> Result<(), String> {
+    let outcome = || {
+        state
+            .smoke_render
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    };
+    if let Some(cached) = outcome().cached(&context, detailed) {
+        return cached;
+    }
+
+    let template = check.template.clone();
+    let input = check.input.clone();
+    let render_context = Arc::clone(&context);
+    let render_options = RenderOptions {
+        timeout: state.options.render_timeout,
+        ..Default::default()
+    };
+    let timeouts = RenderTimeouts::new(&context, &check.template, "pdf");
+    let result = spawn_render(state, timeouts, render_options, move |render_options| {
+        PdfContext::render_pdf(
+            render_context,
+            template,
+            input,
+            &PdfOptions::default(),
+            &render_options,
+        )
+    })
+    .await;
+
+    let failure = match result {
+        Ok(_) => {
+            debug!(template = %check.template, "Readiness smoke render succeeded");
+            None
+        }
+        Err(error) => {
+            warn!(template = %check.template, %error, "Readiness smoke render failed");
+            Some((Instant::now(), error))
+        }
+    };
+    let message = failure
+        .as_ref()
+        .map(|(_, error)| failure_message(error, detailed));
+    *outcome() = SmokeOutcome {
+        context: Arc::downgrade(&context),
+        failure,
+    };
+    message.map_or(Ok(()), Err)
+}
+
+/// Query parameters accepted by the PDF render endpoint.
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct PdfQuery {
+    /// Comma-separated PDF standards, e.g. `a-3b` or `1.7,ua-1`.
+    standards: Option<String>,
+    /// Stable identifier the PDF document ID is derived from.
+    identifier: Option<String>,
+    /// `now`, `none` or an RFC 3339 timestamp.
+    timestamp: Option<PdfTimestamp>,
+    /// 1-based page ranges to export, like `1-3,5`.
+    page_ranges: Option<String>,
+}
+
+impl PdfQuery {
+    /// Convert the query into PDF options, rejecting unknown standards.
+    fn into_options(self) -> AppResult<PdfOptions> {
+        let standards = self
+            .standards
+            .map(|standards| {
+                standards
+                    .split(',')
+                    .map(|standard| {
+                        let standard = standard.trim();
+                        serde_json::from_value(serde_json::Value::from(standard)).map_err(|_| {
+                            AppError::InvalidRenderOptions(format!(
+                                "unknown PDF standard `{standard}`"
+                            ))
+                        })
+                    })
+                    .collect::<AppResult<Vec<_>>>()
+            })
+            .transpose()?;
+
+        Ok(PdfOptions {
+            standards,
+            identifier: self.identifier,
+            timestamp: self.timestamp,
+            page_ranges: self.page_ranges,
+        })
+    }
+}
+
 /// Render a Typst template into a PDF and stream it back to the client.
-#[instrument(skip(pdf_context, input), fields(template = %template, file_name = %file_name))]
+///
+/// Served for both `GET` and `POST`; the request body is the JSON input.
+#[instrument(skip(state, input), fields(template = %template, file_name = %file_name))]
 pub(crate) async fn render_pdf(
-    State(pdf_context): State<Arc<PdfContext>>,
+    State(state): State<AppState>,
     Path((template, file_name)): Path<(String, String)>,
+    Query(query): Query<PdfQuery>,
+    headers: HeaderMap,
     Json(input): Json<serde_json::Value>,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
+    info!(%template, %file_name, ?query, "Received PDF render request");
+    check_file_name(&file_name)?;
+    let pdf_options = query.into_options()?;
+    let render_options = state.render_options(&headers)?;
+    pdf_response(
+        &state,
+        pdf_options,
+        render_options,
+        template,
+        file_name,
+        input,
+    )
+    .await
+}
+
+/// Render a single PDF described by a JSON envelope carrying the template,
+/// file name and input, in the same shape as a batch item.
+#[instrument(skip(state, request), fields(template = %request.template, file_name = %request.file_name))]
+pub(crate) async fn render_pdf_envelope(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<BatchRenderRequest>,
+) -> Result<Response, AppError> {
+    let BatchRenderRequest {
+        template,
+        file_name,
+        input,
+        options,
+        pdf,
+    } = request;
     info!(%template, %file_name, "Received PDF render request");
-    let pdf_bytes = PdfContext::render(pdf_context, template, input)?;
+    check_file_name(&file_name)?;
+
+    let mut render_options = state.render_options(&headers)?;
+    if options.timeout.is_some() {
+        render_options.timeout = state.render_timeout(options.timeout);
+    }
+    render_options.inputs.extend(options.inputs);
+    render_options.now = options.now.or(render_options.now);
+    pdf_response(&state, pdf, render_options, template, file_name, input).await
+}
+
+/// Render a PDF on the blocking pool and wrap it in a download response.
+async fn pdf_response(
+    state: &AppState,
+    pdf_options: PdfOptions,
+    render_options: RenderOptions,
+    template: String,
+    file_name: String,
+    input: serde_json::Value,
+) -> Result<Response, AppError> {
+    let context = state.context.load();
+    let warnings = render_options.warnings.clone();
+    let timeouts = RenderTimeouts::new(&context, &template, "pdf");
+    let pdf_bytes = spawn_render(state, timeouts, render_options, move |render_options| {
+        PdfContext::render_pdf(context, template, input, &pdf_options, &render_options)
+    })
+    .await?;
     debug!("Successfully rendered PDF ({} bytes)", pdf_bytes.len());
 
-    Ok((
+    let response = binary_attachment("application/pdf", &file_name, pdf_bytes);
+    Ok(report_unknown_fonts(response, warnings.as_ref()))
+}
+
+/// Query parameters accepted by the PNG render endpoint.
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct PngQuery {
+    /// 1-based page number; when omitted every page is returned in a ZIP archive.
+    page: Option<usize>,
+    /// Pixels per typographic point, defaults to [`PngOptions::default`].
+    pixel_per_pt: Option<f32>,
+}
+
+/// Render a Typst template into PNG images and return them to the client.
+///
+/// A single page is returned as `image/png`; all pages are returned as a ZIP
+/// archive with one `{stem}-{page}.png` entry per page.
+#[instrument(skip(state, input), fields(template = %template, file_name = %file_name))]
+pub(crate) async fn render_png(
+    State(state): State<AppState>,
+    Path((template, file_name)): Path<(String, String)>,
+    Query(query): Query<PngQuery>,
+    headers: HeaderMap,
+    Json(input): Json<serde_json::Value>,
+) -> Result<Response, AppError> {
+    info!(%template, %file_name, ?query, "Received PNG render request");
+    check_file_name(&file_name)?;
+    let defaults = PngOptions::default();
+    let options = PngOptions {
+        pixel_per_pt: query.pixel_per_pt.unwrap_or(defaults.pixel_per_pt),
+        pages: query.page.map_or(PageSelection::All, PageSelection::Single),
+    };
+    let render_options = state.render_options(&headers)?;
+    let warnings = render_options.warnings.clone();
+    let context = state.context.load();
+    let timeouts = RenderTimeouts::new(&context, &template, "png");
+    let mut images = spawn_render(&state, timeouts, render_options, move |render_options| {
+        PdfContext::render_png(context, template, input, &options, &render_options)
+    })
+    .await?;
+
+    if query.page.is_some()
+        && let Some(png_bytes) = images.pop()
+    {
+        debug!("Successfully rendered PNG ({} bytes)", png_bytes.len());
+        let response = binary_attachment("image/png", &file_name, png_bytes);
+        return Ok(report_unknown_fonts(response, warnings.as_ref()));
+    }
+
+    let zip_bytes = pages_archive(&file_name, "png", &images).await?;
+    debug!(
+        pages = images.len(),
+        "Successfully rendered PNG archive ({} bytes)",
+        zip_bytes.len()
+    );
+
+    let response = binary_attachment("application/zip", &file_name, zip_bytes);
+    Ok(report_unknown_fonts(response, warnings.as_ref()))
+}
+
+/// Query parameters accepted by the SVG render endpoint.
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct SvgQuery {
+    /// 1-based page number; when omitted every page is exported.
+    page: Option<usize>,
+    /// Whether to merge all pages into one SVG, defaults to [`SvgOptions::default`].
+    merge_pages: Option<bool>,
+}
+
+/// Render a Typst template into SVG and return it to the client.
+///
+/// A single page or a merged document is returned as `image/svg+xml`; when
+/// merging is disabled all pages are returned as a ZIP archive with one
+/// `{stem}-{page}.svg` entry per page.
+#[instrument(skip(state, input), fields(template = %template, file_name = %file_name))]
+pub(crate) async fn render_svg(
+    State(state): State<AppState>,
+    Path((template, file_name)): Path<(String, String)>,
+    Query(query): Query<SvgQuery>,
+    headers: HeaderMap,
+    Json(input): Json<serde_json::Value>,
+) -> Result<Response, AppError> {
+    info!(%template, %file_name, ?query, "Received SVG render request");
+    check_file_name(&file_name)?;
+    let defaults = SvgOptions::default();
+    let options = SvgOptions {
+        pages: query.page.map_or(PageSelection::All, PageSelection::Single),
+        merge_pages: query.merge_pages.unwrap_or(defaults.merge_pages),
+    };
+    let merge_pages = options.merge_pages;
+    let render_options = state.render_options(&headers)?;
+    let warnings = render_options.warnings.clone();
+    let context = state.context.load();
+    let timeouts = RenderTimeouts::new(&context, &template, "svg");
+    let mut svgs = spawn_render(&state, timeouts, render_options, move |render_options| {
+        PdfContext::render_svg(context, template, input, &options, &render_options)
+    })
+    .await?;
+
+    if (query.page.is_some() || merge_pages)
+        && let Some(svg) = svgs.pop()
+    {
+        debug!("Successfully rendered SVG ({} bytes)", svg.len());
+        let response = binary_attachment("image/svg+xml", &file_name, svg.into_bytes());
+        return Ok(report_unknown_fonts(response, warnings.as_ref()));
+    }
+
+    let zip_bytes = pages_archive(&file_name, "svg", &svgs).await?;
+    debug!(
+        pages = svgs.len(),
+        "Successfully rendered SVG archive ({} bytes)",
+        zip_bytes.len()
+    );
+
+    let response = binary_attachment("application/zip", &file_name, zip_bytes);
+    Ok(report_unknown_fonts(response, warnings.as_ref()))
+}
+
+/// Pack per-page exports into an in-memory ZIP archive, naming each entry
+/// `{stem}-{page}.{extension}` after the requested file name.
+async fn pages_archive(
+    file_name: &str,
+    extension: &str,
+    pages: &[impl AsRef<[u8]>],
+) -> Result<Vec<u8>, AppError> {
+    let stem = std::path::Path::new(file_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("page");
+    let mut writer = ZipResponseWriter::new(Vec::new());
+    for (index, page) in pages.iter().enumerate() {
+        writer
+            .add_file(&format!("{stem}-{}.{extension}", index + 1), page.as_ref())
+            .await?;
+    }
+    writer.finish().await
+}
+
+/// Reject file names that cannot be sent in the quoted `filename` of a
+/// `Content-Disposition` header, before any rendering work is done for them.
+fn check_file_name(file_name: &str) -> AppResult<()> {
+    if file_name
+        .chars()
+        .any(|c| c.is_control() || c == '"' || c == '\\')
+    {
+        return Err(AppError::InvalidRenderOptions(
+            "file_name must not contain control characters, quotes or backslashes".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Build a download response carrying the given bytes.
+fn binary_attachment(content_type: &str, file_name: &str, bytes: Vec<u8>) -> Response {
+    (
         [
-            (CONTENT_TYPE, "application/pdf".to_string()),
-            (CONTENT_LENGTH, pdf_bytes.len().to_string()),
+            (CONTENT_TYPE, content_type.to_string()),
+            (CONTENT_LENGTH, bytes.len().to_string()),
             (
                 CONTENT_DISPOSITION,
                 format!("attachment; filename=\"{file_name}\""),
             ),
         ],
-        pdf_bytes,
-    ))
+        bytes,
+    )
+        .into_response()
+}
+
+/// List the font families a render could not find in [`UNKNOWN_FONTS_HEADER`].
+///
+/// Families that cannot be sent in a header (e.g. non-ASCII names) are
+/// left out; they still show up in the log and the metrics.
+fn report_unknown_fonts(mut response: Response, warnings: Option<&RenderWarnings>) -> Response {
+    let families = warnings.map(RenderWarnings::unknown_font_families);
+    let Some(families) = families.filter(|families| !families.is_empty()) else {
+        return response;
+    };
+    if let Ok(value) = HeaderValue::from_str(&families.join(", ")) {
+        response.headers_mut().insert(UNKNOWN_FONTS_HEADER, value);
+    }
+    response
+}
+
+/// Rebuild the context from its assets directory and swap it in for new requests.
+///
+/// Responds with `409 Conflict` while another reload is running rather than
+/// holding a blocking thread until it finishes.
+pub(crate) async fn reload(
+    State(context): State<ContextHandle>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    info!("Received reload request");
+    let handle = context.clone();
+    tokio::task::spawn_blocking(telemetry::blocking(move || handle.try_reload())).await??;
+
+    Ok(Json(serde_json::json!({
+        "templates": context.load().template_names(),
+    })))
+}
+
+/// Request header opting into diagnostics when the server runs in
+/// [`DiagnosticsMode::OnRequest`].
+const DIAGNOSTICS_HEADER: &str = "x-typst-diagnostics";
+
+/// Whether diagnostics may be included in the response to a request.
+fn diagnostics_exposed(mode: DiagnosticsMode, headers: &HeaderMap) -> bool {
+    match mode {
+        DiagnosticsMode::Hidden => false,
+        DiagnosticsMode::Always => true,
+        DiagnosticsMode::OnRequest => headers
+            .get(DIAGNOSTICS_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| matches!(value.trim(), "1" | "true" | "yes")),
+    }
+}
+
+/// Middleware replacing error bodies with their diagnostics-enriched variant
+/// when the server configuration or the request asks for it.
+pub(crate) async fn expose_diagnostics(
+    State(mode): State<DiagnosticsMode>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let expose = diagnostics_exposed(mode, request.headers());
+    let mut response = next.run(request).await;
+    if expose && let Some(DetailedErrorBody(body)) = response.extensions_mut().remove() {
+        return (response.status(), Json(body)).into_response();
+    }
+
+    response
+}
+
+/// Query parameters accepted by the batch endpoint.
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct BatchQuery {
+    /// `abort` (default) or `report` to list failed items in a manifest.
+    errors: Option<BatchErrorMode>,
+    /// `completion` (default) or `request` to write entries in request order.
+    order: Option<BatchOrder>,
+    /// `zip` (default) or `pdf` for one merged PDF.
+    output: Option<BatchOutput>,
+}
+
+/// Shape of a batch response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BatchOutput {
+    /// A streamed ZIP archive with one PDF per item.
+    #[default]
+    Zip,
+    /// A single PDF with every item's pages and a bookmark per item.
+    Pdf,
 }
 
-/// Render multiple Typst templates and stream the PDFs as a ZIP archive.
-#[instrument(skip(pdf_context, requests))]
+/// Render multiple Typst templates and stream the PDFs as a ZIP archive, or
+/// merge them into a single PDF.
+#[instrument(skip(state, headers, requests))]
 pub(crate) async fn render_pdf_batch(
-    State(pdf_context): State<Arc<PdfContext>>,
-    Json(requests): Json<Vec<BatchRenderRequest>>,
-) -> Result<impl IntoResponse, AppError> {
-    info!(count = requests.len(), "Received batch PDF render request");
+    State(state): State<AppState>,
+    Query(query): Query<BatchQuery>,
+    headers: HeaderMap,
+    Json(mut requests): Json<Vec<BatchRenderRequest>>,
+) -> Result<Response, AppError> {
+    info!(
+        count = requests.len(),
+        ?query,
+        "Received batch PDF render request"
+    );
 
-    let stream = PdfContext::render_batch(pdf_context, requests)?;
+    let options = state.batch_options(&mut requests, query.errors, query.order, &headers);
 
+    if query.output.unwrap_or_default() == BatchOutput::Pdf {
+        let pdf_bytes =
+            PdfContext::render_batch_merged(state.context.load(), requests, &options).await?;
+        debug!("Successfully merged batch PDF ({} bytes)", pdf_bytes.len());
+        return Ok(binary_attachment(
+            "application/pdf",
+            BATCH_MERGED_NAME,
+            pdf_bytes,
+        ));
+    }
+
+    let stream = PdfContext::render_batch_with_options(state.context.load(), requests, options)?;
     Ok(Attachment::new(Body::from_stream(stream))
         .filename(BATCH_ARCHIVE_NAME)
-        .content_type("application/zip"))
+        .content_type("application/zip")
+        .into_response())
+}
+
+/// Query parameters accepted when submitting a job.
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct JobQuery {
+    /// `abort` (default) or `report` to list failed items in a manifest.
+    errors: Option<BatchErrorMode>,
+    /// `completion` (default) or `request` to write entries in request order.
+    order: Option<BatchOrder>,
+    /// URL to POST the job's final status to once it finishes.
+    callback: Option<String>,
+}
+
+/// Start rendering a batch in the background and return the new job.
+///
+/// Responds with `202 Accepted` and a `Location` header pointing at the job's
+/// status; the archive is downloaded from `/jobs/{id}/result` once done.
+#[instrument(skip(state, headers, requests))]
+pub(crate) async fn submit_job(
+    State(state): State<AppState>,
+    Query(query): Query<JobQuery>,
+    headers: HeaderMap,
+    Json(mut requests): Json<Vec<BatchRenderRequest>>,
+) -> Result<Response, AppError> {
+    info!(count = requests.len(), ?query, "Received batch job");
+    let options = state.batch_options(&mut requests, query.errors, query.order, &headers);
+    let context = state.context.load();
+    let status = match query.callback {
+        #[cfg(feature = "webhooks")]
+        Some(callback) => state
+            .jobs
+            .submit_with_callback(context, requests, options, &callback)?,
+        #[cfg(not(feature = "webhooks"))]
+        Some(_) => {
+            return Err(AppError::InvalidRenderOptions(
+                "callbacks require the `webhooks` feature".into(),
+            ));
+        }
+        None => state.jobs.submit(context, requests, options)?,
+    };
+
+    Ok((
+        StatusCode::ACCEPTED,
+        [(LOCATION, format!("/jobs/{}", status.id))],
+        Json(status),
+    )
+        .into_response())
+}
+
+/// Report a job's state and progress.
+pub(crate) async fn job_status(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<JobStatus>, AppError> {
+    Ok(Json(state.jobs.status(id)?))
+}
+
+/// Stream a completed job's ZIP archive from disk.
+pub(crate) async fn job_result(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let path = state.jobs.result_path(id)?;
+    let file = tokio::fs::File::open(&path).await?;
+    let length = file.metadata().await?.len();
+
+    Ok((
+        [(CONTENT_LENGTH, length.to_string())],
+        Attachment::new(Body::from_stream(ReaderStream::new(file)))
+            .filename(BATCH_ARCHIVE_NAME)
+            .content_type("application/zip"),
+    )
+        .into_response())
+}
+
+/// Cancel a job if it is still running and delete its archive.
+pub(crate) async fn delete_job(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    state.jobs.remove(id)?;
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -0,0 +1,466 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use tokio::task::AbortHandle;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+#[cfg(feature = "webhooks")]
+use crate::webhook::{Webhook, WebhookOptions};
+use crate::{
+    diagnostics::Diagnostic,
+    error::{AppError, AppResult},
+    pdf::{BatchOptions, BatchProgress, BatchRenderRequest, PdfContext, describe_failure},
+    zip::ZipResponseWriter,
+};
+
+/// How long a finished job is kept when no TTL is configured.
+pub const DEFAULT_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Extension of a finished job archive.
+const RESULT_EXTENSION: &str = "zip";
+
+/// Extension of an archive that is still being written.
+const PARTIAL_EXTENSION: &str = "zip.part";
+
+/// Where job results are stored and how long they are kept.
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    /// Directory the job archives are written to; created on first use.
+    pub directory: PathBuf,
+    /// How long a finished job and its archive are kept before they expire.
+    pub ttl: Duration,
+    /// Delivery of the callbacks requested with [`JobStore::submit_with_callback`].
+    #[cfg(feature = "webhooks")]
+    pub webhook: WebhookOptions,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            directory: std::env::temp_dir().join("typst-webservice-jobs"),
+            ttl: DEFAULT_JOB_TTL,
+            #[cfg(feature = "webhooks")]
+            webhook: WebhookOptions::default(),
+        }
+    }
+}
+
+/// Lifecycle of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    /// The batch is still rendering.
+    Running,
+    /// The archive is ready for download.
+    Completed,
+    /// The batch failed; see [`JobStatus::error`].
+    Failed,
+}
+
+/// A snapshot of a job as returned when polling it.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobStatus {
+    pub id: Uuid,
+    pub state: JobState,
+    /// Number of items in the batch.
+    pub total: usize,
+    /// Items written to the archive so far.
+    pub completed: usize,
+    /// Items that failed to render so far.
+    pub failed: usize,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// When the job and its archive are removed; set once it has finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Body of the callback sent when a job finishes.
+#[cfg(feature = "webhooks")]
+#[derive(Serialize)]
+struct JobNotification<'a> {
+    #[serde(flatten)]
+    job: &'a JobStatus,
+    /// Where to download the archive; only set for completed jobs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<String>,
+}
+
+/// Book-keeping for one submitted batch.
+struct Job {
+    state: JobState,
+    total: usize,
+    progress: BatchProgress,
+    created_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    error: Option<String>,
+    diagnostics: Vec<Diagnostic>,
+    task: Option<AbortHandle>,
+    #[cfg(feature = "webhooks")]
+    callback: Option<reqwest::Url>,
+}
+
+impl Job {
+    fn new(total: usize) -> Self {
+        Job {
+            state: JobState::Running,
+            total,
+            progress: BatchProgress::default(),
+            created_at: Utc::now(),
+            finished_at: None,
+            error: None,
+            diagnostics: Vec::new(),
+            task: None,
+            #[cfg(feature = "webhooks")]
+            callback: None,
+        }
+    }
+
+    fn status(&self, id: Uuid, ttl: Duration) -> JobStatus {
+        JobStatus {
+            id,
+            state: self.state,
+            total: self.total,
+            completed: self.progress.completed(),
+            failed: self.progress.failed(),
+            created_at: self.created_at,
+            finished_at: self.finished_at,
+            expires_at: self
+                .finished_at
+                .and_then(|finished_at| Some(finished_at + TimeDelta::from_std(ttl).ok()?)),
+            error: self.error.clone(),
+            diagnostics: self.diagnostics.clone(),
+        }
+    }
+}
+
+/// Runs batches in the background and keeps their archives on disk until
+/// they expire.
+///
+/// Jobs live in memory, so they do not survive a restart; archives a previous
+/// process left behind are deleted once they are older than the TTL.
+#[derive(Clone)]
+pub struct JobStore {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    options: JobOptions,
+    jobs: Mutex<HashMap<Uuid, Job>>,
+    #[cfg(feature = "webhooks")]
+    webhook: Webhook,
+}
+
+impl JobStore {
+    /// Create a store writing into `options.directory`.
+    pub fn new(options: JobOptions) -> Self {
+        remove_stale_archives(&options.directory, options.ttl);
+        Self {
+            inner: Arc::new(Inner {
+                #[cfg(feature = "webhooks")]
+                webhook: Webhook::new(options.webhook.clone()),
+                options,
+                jobs: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// The options the store was created with.
+    pub fn options(&self) -> &JobOptions {
+        &self.inner.options
+    }
+
+    /// Validate a batch and start rendering it in the background.
+    ///
+    /// Validation errors are returned right away, like for
+    /// [`PdfContext::render_batch`]; everything after that is reported through
+    /// [`JobStore::status`]. Must be called from within a Tokio runtime.
+    pub fn submit(
+        &self,
+        context: Arc<PdfContext>,
+        requests: Vec<BatchRenderRequest>,
+        options: BatchOptions,
+    ) -> AppResult<JobStatus> {
+        let job = Job::new(requests.len());
+        self.start(context, requests, options, job)
+    }
+
+    /// Like [`JobStore::submit`], but POST the job's final status to
+    /// `callback_url` once it completes or fails.
+    ///
+    /// Callbacks are retried, signed, and restricted to the hosts configured
+    /// in [`JobOptions::webhook`]; a job counts as finished whether or not
+    /// its callback could be delivered.
+    #[cfg(feature = "webhooks")]
+    pub fn submit_with_callback(
+        &self,
+        context: Arc<PdfContext>,
+        requests: Vec<BatchRenderRequest>,
+        options: BatchOptions,
+        callback_url: &str,
+    ) -> AppResult<JobStatus> {
+        let mut job = Job::new(requests.len());
+        job.callback = Some(self.inner.webhook.parse_url(callback_url)?);
+        self.start(context, requests, options, job)
+    }
+
+    fn start(
+        &self,
+        context: Arc<PdfContext>,
+        requests: Vec<BatchRenderRequest>,
+        mut options: BatchOptions,
+        job: Job,
+    ) -> AppResult<JobStatus> {
+        context.validate_batch_with_options(&requests, &options)?;
+        std::fs::create_dir_all(&self.inner.options.directory)?;
+
+        let id = Uuid::new_v4();
+        options.progress = Some(job.progress.clone());
+        let status = job.status(id, self.inner.options.ttl);
+        self.jobs().insert(id, job);
+
+        let store = self.clone();
+        let task = tokio::spawn(async move { store.run(id, context, requests, options).await });
+        if let Some(job) = self.jobs().get_mut(&id) {
+            job.task = Some(task.abort_handle());
+        }
+
+        info!(%id, total = status.total, "Submitted batch job");
+        Ok(status)
+    }
+
+    /// The current status of a job.
+    pub fn status(&self, id: Uuid) -> AppResult<JobStatus> {
+        self.jobs()
+            .get(&id)
+            .map(|job| job.status(id, self.inner.options.ttl))
+            .ok_or(AppError::JobNotFound(id))
+    }
+
+    /// Path of a completed job's archive.
+    pub fn result_path(&self, id: Uuid) -> AppResult<PathBuf> {
+        match self.jobs().get(&id) {
+            Some(job) if job.state == JobState::Completed => Ok(self.path(id, RESULT_EXTENSION)),
+            Some(_) => Err(AppError::JobResultUnavailable(id)),
+            None => Err(AppError::JobNotFound(id)),
+        }
+    }
+
+    /// Cancel a job if it is still running and delete it with its archive.
+    pub fn remove(&self, id: Uuid) -> AppResult<()> {
+        let job = self.jobs().remove(&id).ok_or(AppError::JobNotFound(id))?;
+        if let Some(task) = job.task {
+            task.abort();
+        }
+        self.delete_files(id);
+        info!(%id, "Removed batch job");
+        Ok(())
+    }
+
+    /// Render the batch, record the outcome, and expire the job after the TTL.
+    async fn run(
+        self,
+        id: Uuid,
+        context: Arc<PdfContext>,
+        requests: Vec<BatchRenderRequest>,
+        options: BatchOptions,
+    ) {
+        let partial = self.path(id, PARTIAL_EXTENSION);
+        let outcome = async {
+            let file = tokio::fs::File::create(&partial).await?;
+            let writer = ZipResponseWriter::new(file);
+            PdfContext::render_batch_to_writer_with_options(context, requests, &options, writer)
+                .await?;
+            tokio::fs::rename(&partial, self.path(id, RESULT_EXTENSION)).await?;
+            Ok::<_, AppError>(())
+        }
+        .await;
+
+        if let Some(job) = self.jobs().get_mut(&id) {
+            job.finished_at = Some(Utc::now());
+            match outcome {
+                Ok(()) => {
+                    info!(%id, "Batch job completed");
+                    job.state = JobState::Completed;
+                }
+                Err(error) => {
+                    warn!(%id, ?error, "Batch job failed");
+                    let (message, diagnostics) = describe_failure(error, options.diagnostics);
+                    job.state = JobState::Failed;
+                    job.error = Some(message);
+                    if options.diagnostics {
+                        job.diagnostics = diagnostics;
+                    }
+                    let _ = std::fs::remove_file(&partial);
+                }
+            }
+        }
+        #[cfg(feature = "webhooks")]
+        self.notify(id);
+
+        tokio::time::sleep(self.inner.options.ttl).await;
+        if self.jobs().remove(&id).is_some() {
+            self.delete_files(id);
+            debug!(%id, "Batch job expired");
+        }
+    }
+
+    /// Send the job's final status to its callback URL, if it has one.
+    #[cfg(feature = "webhooks")]
+    fn notify(&self, id: Uuid) {
+        let Some((url, status)) = self.jobs().get(&id).and_then(|job| {
+            let url = job.callback.clone()?;
+            Some((url, job.status(id, self.inner.options.ttl)))
+        }) else {
+            return;
+        };
+
+        let webhook = self.inner.webhook.clone();
+        // Matches the download route served by the HTTP server.
+        let result = (status.state == JobState::Completed)
+            .then(|| webhook.location(&format!("/jobs/{id}/result")));
+        let body = match serde_json::to_vec(&JobNotification {
+            job: &status,
+            result,
+        }) {
+            Ok(body) => body,
+            Err(error) => {
+                warn!(%id, %error, "Failed to serialize job callback");
+                return;
+            }
+        };
+        tokio::spawn(async move { webhook.deliver(url, body).await });
+    }
+
+    fn jobs(&self) -> MutexGuard<'_, HashMap<Uuid, Job>> {
+        self.inner
+            .jobs
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+
+    fn path(&self, id: Uuid, extension: &str) -> PathBuf {
+        self.inner
+            .options
+            .directory
+            .join(format!("{id}.{extension}"))
+    }
+
+    fn delete_files(&self, id: Uuid) {
+        for extension in [RESULT_EXTENSION, PARTIAL_EXTENSION] {
+            let _ = std::fs::remove_file(self.path(id, extension));
+        }
+    }
+}
+
+/// Delete job archives in `directory` last modified more than `ttl` ago.
+///
+/// Only files named like a job archive are touched, so the directory may be
+/// shared with other data or with other instances of the service.
+fn remove_stale_archives(directory: &Path, ttl: Duration) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_archive = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split_once('.'))
+            .is_some_and(|(stem, extension)| {
+                Uuid::parse_str(stem).is_ok()
+                    && (extension == RESULT_EXTENSION || extension == PARTIAL_EXTENSION)
+            });
+        let expired = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > ttl);
+
+        if is_archive && expired {
+            match std::fs::remove_file(&path) {
+                Ok(()) => debug!(path = %path.display(), "Removed stale job archive"),
+                Err(error) => {
+                    warn!(path = %path.display(), %error, "Failed to remove stale job archive")
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{JobOptions, JobState, JobStore};
+    use crate::{
+        error::AppError,
+        pdf::{BatchOptions, BatchRenderRequest, PdfContext},
+    };
+
+    /// A finished job exposes its archive until the TTL runs out.
+    #[tokio::test]
+    async fn job_completes_and_expires() {
+        crate::logging::init_for_tests();
+        let directory = std::env::temp_dir().join(format!("tws-jobs-{}", uuid::Uuid::new_v4()));
+        let store = JobStore::new(JobOptions {
+            directory: directory.clone(),
+            ttl: Duration::from_millis(300),
+            #[cfg(feature = "webhooks")]
+            webhook: Default::default(),
+        });
+        let context =
+            Arc::new(PdfContext::from_assets(&[("hello.typ", b"Hello".as_slice())]).unwrap());
+        let requests = vec![BatchRenderRequest {
+            template: "hello.typ".to_string(),
+            file_name: "hello.pdf".to_string(),
+            ..Default::default()
+        }];
+
+        let submitted = store
+            .submit(context, requests, BatchOptions::default())
+            .unwrap();
+        assert_eq!(submitted.state, JobState::Running);
+        assert!(matches!(
+            store.result_path(submitted.id),
+            Err(AppError::JobResultUnavailable(_))
+        ));
+
+        let status = loop {
+            let status = store.status(submitted.id).unwrap();
+            if status.state != JobState::Running {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(status.state, JobState::Completed);
+        assert_eq!((status.total, status.completed), (1, 1));
+        assert!(status.expires_at.is_some());
+
+        let path = store.result_path(submitted.id).unwrap();
+        let archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(archive.len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert!(matches!(
+            store.status(submitted.id),
+            Err(AppError::JobNotFound(_))
+        ));
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use lru::LruCache;
+use tracing::{debug, trace, warn};
+use typst::{
+    diag::{FileError, FileResult},
+    foundations::Bytes,
+    syntax::{FileId, VirtualRoot},
+};
+
+/// Binary assets that stay on disk until a render first reads them.
+///
+/// Reads are kept in a least-recently-used cache bounded by the total size of
+/// the cached files; files larger than the whole cache are never cached.
+pub(crate) struct LazyAssets {
+    /// Canonical assets directory; no file outside it is ever read.
+    root: PathBuf,
+    /// File on disk for each id, keyed like eagerly loaded assets.
+    files: HashMap<FileId, PathBuf>,
+    capacity: usize,
+    cache: Mutex<Cache>,
+}
+
+struct Cache {
+    files: LruCache<FileId, Bytes>,
+    /// Total size of the cached files in bytes.
+    size: usize,
+}
+
+impl LazyAssets {
+    /// Serve `files` from below the canonical directory `root`, caching up
+    /// to `capacity` bytes of them.
+    pub(crate) fn new(root: PathBuf, files: HashMap<FileId, PathBuf>, capacity: usize) -> Self {
+        Self {
+            root,
+            files,
+            capacity,
+            cache: Mutex::new(Cache {
+                files: LruCache::unbounded(),
+                size: 0,
+            }),
+        }
+    }
+
+    /// Read a project file, from the cache if possible.
+    pub(crate) fn get(&self, id: FileId) -> FileResult<Bytes> {
+        if let Some(bytes) = self.lock().files.get(&id) {
+            trace!(?id, "Served cached asset");
+            return Ok(bytes.clone());
+        }
+
+        let path = self.resolve(id)?;
+        let vpath = Path::new(id.vpath().get_without_slash());
+        let bytes = fs::read(&path)
+            .map(Bytes::new)
+            .map_err(|error| FileError::from_io(error, vpath))?;
+        debug!(file = %vpath.display(), size = bytes.len(), "Read asset from disk");
+        self.insert(id, bytes.clone());
+        Ok(bytes)
+    }
+
+    /// Map a project file to its path on disk, refusing paths that leave the
+    /// assets directory, e.g. through a symlink.
+    fn resolve(&self, id: FileId) -> FileResult<PathBuf> {
+        let vpath = Path::new(id.vpath().get_without_slash());
+        let file = match id.root() {
+            VirtualRoot::Project => self.files.get(&id),
+            VirtualRoot::Package(_) => None,
+        };
+        let Some(file) = file else {
+            return Err(FileError::NotFound(vpath.into()));
+        };
+
+        let path = fs::canonicalize(file).map_err(|error| FileError::from_io(error, vpath))?;
+        if !path.starts_with(&self.root) {
+            warn!(file = %vpath.display(), "Refusing to read asset outside the assets directory");
+            return Err(FileError::AccessDenied);
+        }
+        Ok(path)
+    }
+
+    /// Cache `bytes`, evicting the least recently used files to stay within
+    /// the capacity.
+    fn insert(&self, id: FileId, bytes: Bytes) {
+        if bytes.len() > self.capacity {
+            return;
+        }
+
+        let mut cache = self.lock();
+        cache.size += bytes.len();
+        if let Some(previous) = cache.files.put(id, bytes) {
+            cache.size -= previous.len();
+        }
+        while cache.size > self.capacity {
+            let Some((evicted, bytes)) = cache.files.pop_lru() else {
+                break;
+            };
+            trace!(id = ?evicted, "Evicted cached asset");
+            cache.size -= bytes.len();
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LazyAssets;
+    use crate::assets::{collect_dir_contents_lazily, file_id_from_path};
+    use std::path::{Path, PathBuf};
+    use typst::diag::FileError;
+
+    /// Index the binary assets below `root` like a lazy context does.
+    fn lazy_assets(root: PathBuf, capacity: usize) -> LazyAssets {
+        let files = collect_dir_contents_lazily(&root).unwrap().deferred;
+        LazyAssets::new(root, files, capacity)
+    }
+
+    /// Files are read on first use and the cache never exceeds its capacity.
+    #[test]
+    fn reads_on_demand_within_capacity() {
+        crate::logging::init_for_tests();
+        let root = std::env::temp_dir().join(format!("tws-lazy-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("images")).unwrap();
+        std::fs::write(root.join("images/a.bin"), [1; 6]).unwrap();
+        std::fs::write(root.join("images/b.bin"), [2; 6]).unwrap();
+        std::fs::write(root.join("huge.bin"), [3; 20]).unwrap();
+        let root = std::fs::canonicalize(root).unwrap();
+        let assets = lazy_assets(root.clone(), 10);
+        let id = |path: &str| file_id_from_path(Path::new(path)).unwrap();
+
+        assert_eq!(assets.get(id("a.bin")).unwrap().as_slice(), [1; 6]);
+        assert_eq!(assets.lock().size, 6);
+
+        // Changes on disk are not seen while the file is cached.
+        std::fs::write(root.join("images/a.bin"), [9; 6]).unwrap();
+        assert_eq!(assets.get(id("a.bin")).unwrap().as_slice(), [1; 6]);
+
+        assert_eq!(assets.get(id("b.bin")).unwrap().as_slice(), [2; 6]);
+        assert_eq!(assets.lock().size, 6, "a.bin should have been evicted");
+        assert_eq!(assets.get(id("a.bin")).unwrap().as_slice(), [9; 6]);
+
+        assert_eq!(assets.get(id("huge.bin")).unwrap().len(), 20);
+        assert!(assets.lock().size <= 10);
+
+        assert!(matches!(
+            assets.get(id("missing.bin")),
+            Err(FileError::NotFound(path)) if path == Path::new("missing.bin")
+        ));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Symlinks cannot be used to read files outside the assets directory.
+    #[cfg(unix)]
+    #[test]
+    fn refuses_paths_outside_the_root() {
+        crate::logging::init_for_tests();
+        let dir = std::env::temp_dir().join(format!("tws-lazy-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("assets/link.txt")).unwrap();
+        let root = std::fs::canonicalize(dir.join("assets")).unwrap();
+        let assets = lazy_assets(root, 1024);
+
+        let link = file_id_from_path(Path::new("link.txt")).unwrap();
+        assert!(matches!(assets.get(link), Err(FileError::AccessDenied)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
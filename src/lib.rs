@@ -1,14 +1,42 @@
 pub use crate::{
+    catalog::TemplateInfo,
     error::AppError,
-    pdf::{BatchRenderRequest, PdfContext},
+    fonts::{FontCoverage, FontDescription, FontSource},
+    jobs::{JobOptions, JobState, JobStatus, JobStore},
+    limit::RenderLimiter,
+    pdf::{
+        BatchErrorMode, BatchOptions, BatchOrder, BatchProgress, BatchRenderRequest,
+        ContextOptions, PageSelection, PdfContext, PdfOptions, PdfTimestamp, PngOptions,
+        RenderOptions, RenderWarnings, SvgOptions,
+    },
+    reload::ContextHandle,
+    schema::SchemaViolation,
 };
+pub use typst_pdf::PdfStandard;
+
+#[cfg(feature = "hot-reload")]
+pub use crate::reload::ContextWatcher;
+#[cfg(feature = "webhooks")]
+pub use crate::webhook::WebhookOptions;
 
 pub const CRATE_INFO: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
 
 mod assets;
+pub mod catalog;
+pub mod diagnostics;
 mod error;
+mod fonts;
+pub mod jobs;
+mod lazy;
+pub mod limit;
 pub mod logging;
+mod merge;
 pub mod pdf;
+pub mod reload;
+pub mod schema;
+mod telemetry;
+#[cfg(feature = "webhooks")]
+pub mod webhook;
 pub mod zip;
 
 #[cfg(feature = "server")]
@@ -18,35 +46,147 @@ pub mod handlers;
 mod tests;
 
 #[cfg(feature = "server")]
-pub use server::start_server;
+pub use server::{
+    DiagnosticsMode, ReadinessCheck, ServerOptions, start_server, start_server_with_options,
+};
 
 #[cfg(feature = "server")]
 mod server {
-    use std::{io, sync::Arc};
+    use std::{io, num::NonZeroUsize, str::FromStr, sync::Arc, time::Duration};
 
     use axum::{
-        Router,
+        Router, middleware,
         routing::{get, post},
     };
     use tokio::net::TcpListener;
     use tracing::info;
 
-    use crate::{error::AppError, handlers, pdf::PdfContext};
+    use crate::{
+        error::AppError,
+        handlers::{self, AppState},
+        jobs::{JobOptions, JobStore},
+        limit::RenderLimiter,
+        reload::ContextHandle,
+    };
 
-    /// Launch the HTTP server and publish the PDF rendering endpoint.
-    pub async fn start_server(
-        listener: TcpListener,
-        pdf_context: PdfContext,
-    ) -> Result<(), AppError> {
-        let pdf_context = Arc::new(pdf_context);
-        let router = Router::new()
+    /// Controls whether resolved Typst diagnostics are included in error responses.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub enum DiagnosticsMode {
+        /// Never include diagnostics; only the public message and reference are returned.
+        #[default]
+        Hidden,
+        /// Include diagnostics when the request sets the `x-typst-diagnostics` header.
+        OnRequest,
+        /// Always include diagnostics.
+        Always,
+    }
+
+    impl FromStr for DiagnosticsMode {
+        type Err = String;
+
+        fn from_str(value: &str) -> Result<Self, Self::Err> {
+            match value.to_ascii_lowercase().as_str() {
+                "hidden" | "off" => Ok(DiagnosticsMode::Hidden),
+                "on-request" | "header" => Ok(DiagnosticsMode::OnRequest),
+                "always" | "on" => Ok(DiagnosticsMode::Always),
+                other => Err(format!("unknown diagnostics mode `{other}`")),
+            }
+        }
+    }
+
+    /// Server-wide settings for the HTTP server.
+    #[derive(Debug, Clone, Default)]
+    pub struct ServerOptions {
+        /// Whether Typst diagnostics are exposed in error responses.
+        pub diagnostics: DiagnosticsMode,
+        /// Default time limit for a single render; requests may override it
+        /// with the `x-render-timeout-ms` header or a batch item's `timeout_ms`.
+        pub render_timeout: Option<Duration>,
+        /// Limit on renders in flight across all requests, single and batch.
+        pub render_limiter: Option<RenderLimiter>,
+        /// Maximum renders of one batch in flight at once; defaults to the
+        /// number of available CPUs.
+        pub batch_concurrency: Option<NonZeroUsize>,
+        /// Storage and expiry of background jobs submitted to `/jobs`.
+        pub jobs: JobOptions,
+        /// Smoke render `/readyz` runs before reporting the server ready.
+        pub readiness: Option<ReadinessCheck>,
+    }
+
+    /// A render that has to succeed before `/readyz` reports the server ready.
+    ///
+    /// It runs once per loaded context: after a success, probes are answered
+    /// from the cached result until the context is reloaded, and after a
+    /// failure for 30 seconds before it runs again.
+    #[derive(Debug, Clone)]
+    pub struct ReadinessCheck {
+        /// Template to render.
+        pub template: String,
+        /// JSON input passed to the template.
+        pub input: serde_json::Value,
+    }
+
+    /// Build the router serving every HTTP endpoint.
+    pub(crate) fn router(context: ContextHandle, options: &ServerOptions) -> Router {
+        Router::new()
             .route("/", get(handlers::root))
+            .route("/healthz", get(handlers::healthz))
+            .route("/readyz", get(handlers::readyz))
+            .route("/metrics", get(handlers::metrics))
+            .route("/templates", get(handlers::templates))
+            .route("/fonts", get(handlers::fonts))
+            .route("/render-pdf", post(handlers::render_pdf_envelope))
             .route(
                 "/render-pdf/{template}/{file_name}",
-                get(handlers::render_pdf),
+                get(handlers::render_pdf).post(handlers::render_pdf),
+            )
+            .route(
+                "/render-png/{template}/{file_name}",
+                get(handlers::render_png).post(handlers::render_png),
+            )
+            .route(
+                "/render-svg/{template}/{file_name}",
+                get(handlers::render_svg).post(handlers::render_svg),
             )
             .route("/render-pdf/batch", post(handlers::render_pdf_batch))
-            .with_state(pdf_context);
+            .route("/jobs", post(handlers::submit_job))
+            .route(
+                "/jobs/{id}",
+                get(handlers::job_status).delete(handlers::delete_job),
+            )
+            .route("/jobs/{id}/result", get(handlers::job_result))
+            .route("/reload", post(handlers::reload))
+            .layer(middleware::from_fn_with_state(
+                options.diagnostics,
+                handlers::expose_diagnostics,
+            ))
+            .with_state(AppState {
+                context,
+                jobs: JobStore::new(options.jobs.clone()),
+                smoke_render: Arc::default(),
+                metrics: crate::telemetry::prometheus_handle(),
+                options: Arc::new(options.clone()),
+            })
+    }
+
+    /// Launch the HTTP server and publish the PDF rendering endpoint.
+    pub async fn start_server(
+        listener: TcpListener,
+        context: impl Into<ContextHandle>,
+    ) -> Result<(), AppError> {
+        start_server_with_options(listener, context, ServerOptions::default()).await
+    }
+
+    /// Launch the HTTP server with explicit [`ServerOptions`].
+    ///
+    /// Pass a [`ContextHandle`] to keep the ability to swap the context (e.g.
+    /// via [`ContextHandle::watch`]) while the server is running.
+    pub async fn start_server_with_options(
+        listener: TcpListener,
+        context: impl Into<ContextHandle>,
+        options: ServerOptions,
+    ) -> Result<(), AppError> {
+        let router = router(context.into(), &options);
 
         info!("HTTP listener ready; serving requests");
         if let Err(error) = axum::serve(listener, router).await {
@@ -0,0 +1,47 @@
+use std::{num::NonZeroUsize, sync::Arc, time::Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::telemetry::WaitingRender;
+
+/// Caps how many renders run at the same time.
+///
+/// Clones share the same budget, so handing one limiter to single renders and
+/// batches bounds the total work on the blocking pool across all requests.
+#[derive(Debug, Clone)]
+pub struct RenderLimiter {
+    semaphore: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl RenderLimiter {
+    /// Allow at most `limit` renders in flight.
+    pub fn new(limit: NonZeroUsize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit.get())),
+            limit: limit.get(),
+        }
+    }
+
+    /// The maximum number of concurrent renders.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// How many more renders could start right now.
+    pub fn available(&self) -> usize {
+        self.semaphore.available_permits()
+    }
+
+    /// Wait for a free slot; the slot is released when the permit is dropped.
+    pub(crate) async fn acquire(&self) -> OwnedSemaphorePermit {
+        let waiting = WaitingRender::start();
+        let start = Instant::now();
+        let permit = Arc::clone(&self.semaphore)
+            .acquire_owned()
+            .await
+            .expect("render semaphore is never closed");
+        waiting.acquired(start.elapsed());
+        permit
+    }
+}
@@ -1,9 +1,13 @@
-use std::env;
+use std::{env, num::NonZeroUsize, path::PathBuf, time::Duration};
 
+use chrono::{DateTime, Utc};
 use tokio::net::TcpListener;
 use tracing::info;
 
-use typst_webservice::{AppError, CRATE_INFO, logging, pdf::PdfContext, start_server};
+use typst_webservice::{
+    AppError, CRATE_INFO, ContextHandle, ContextOptions, DiagnosticsMode, JobOptions,
+    ReadinessCheck, RenderLimiter, ServerOptions, logging, start_server_with_options,
+};
 
 const DEFAULT_ASSETS_DIR: &str = "assets";
 const ASSETS_DIR_ENV_VAR: &str = "TWS_DIR";
@@ -13,6 +17,35 @@ const DEFAULT_HOST: &str = "127.0.0.1";
 
 const HOST_ENV_VAR: &str = "TWS_HOST";
 const PORT_ENV_VAR: &str = "TWS_PORT";
+const DIAGNOSTICS_ENV_VAR: &str = "TWS_DIAGNOSTICS";
+const WATCH_ENV_VAR: &str = "TWS_WATCH";
+const RENDER_TIMEOUT_ENV_VAR: &str = "TWS_RENDER_TIMEOUT_MS";
+const PACKAGE_DIR_ENV_VAR: &str = "TWS_PACKAGE_DIR";
+const FIXED_NOW_ENV_VAR: &str = "TWS_FIXED_NOW";
+const FONT_DIRS_ENV_VAR: &str = "TWS_FONT_DIRS";
+const SYSTEM_FONTS_ENV_VAR: &str = "TWS_SYSTEM_FONTS";
+const EMBEDDED_FONTS_ENV_VAR: &str = "TWS_EMBEDDED_FONTS";
+const LAZY_ASSETS_ENV_VAR: &str = "TWS_LAZY_ASSETS_MB";
+const MAX_CONCURRENT_RENDERS_ENV_VAR: &str = "TWS_MAX_CONCURRENT_RENDERS";
+const BATCH_CONCURRENCY_ENV_VAR: &str = "TWS_BATCH_CONCURRENCY";
+const JOB_DIR_ENV_VAR: &str = "TWS_JOB_DIR";
+const JOB_TTL_ENV_VAR: &str = "TWS_JOB_TTL_SECS";
+const READY_TEMPLATE_ENV_VAR: &str = "TWS_READY_TEMPLATE";
+const READY_INPUT_ENV_VAR: &str = "TWS_READY_INPUT";
+#[cfg(feature = "webhooks")]
+const WEBHOOK_SECRET_ENV_VAR: &str = "TWS_WEBHOOK_SECRET";
+#[cfg(feature = "webhooks")]
+const WEBHOOK_RETRIES_ENV_VAR: &str = "TWS_WEBHOOK_RETRIES";
+#[cfg(feature = "webhooks")]
+const WEBHOOK_ALLOWED_HOSTS_ENV_VAR: &str = "TWS_WEBHOOK_ALLOWED_HOSTS";
+#[cfg(feature = "webhooks")]
+const WEBHOOK_ALLOW_PRIVATE_ENV_VAR: &str = "TWS_WEBHOOK_ALLOW_PRIVATE";
+#[cfg(feature = "webhooks")]
+const PUBLIC_URL_ENV_VAR: &str = "TWS_PUBLIC_URL";
+
+/// Quiet period after the last file change before the assets are reloaded.
+#[cfg(feature = "hot-reload")]
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
 
 #[cfg(test)]
 mod cli_tests;
@@ -34,14 +67,184 @@ async fn main() -> Result<(), AppError> {
 
     let assets_dir = resolve_assets_dir(cli_args.assets_dir);
     info!(%assets_dir, "Loading Typst assets");
-    let pdf_context = PdfContext::from_directory(&assets_dir)?;
+    let context_options = ContextOptions {
+        packages: env::var_os(PACKAGE_DIR_ENV_VAR)
+            .filter(|value| !value.is_empty())
+            .map(Into::into),
+        now: resolve_fixed_now(),
+        font_dirs: resolve_font_dirs(),
+        system_fonts: env_flag(SYSTEM_FONTS_ENV_VAR),
+        embedded_fonts: !env::var(EMBEDDED_FONTS_ENV_VAR)
+            .is_ok_and(|value| matches!(value.trim(), "0" | "false" | "no")),
+        lazy_assets: resolve_lazy_assets(),
+    };
+    let context = ContextHandle::from_directory_with_options(&assets_dir, context_options)?;
+
+    #[cfg(feature = "hot-reload")]
+    let _watcher = if cli_args.watch || env_flag(WATCH_ENV_VAR) {
+        Some(context.watch(WATCH_DEBOUNCE)?)
+    } else {
+        None
+    };
+    #[cfg(not(feature = "hot-reload"))]
+    if cli_args.watch || env_flag(WATCH_ENV_VAR) {
+        tracing::warn!("Watching requires the `hot-reload` feature; ignoring");
+    }
 
     let addr = resolve_addr(cli_args.addr);
 
     info!("Binding HTTP listener on {}", addr);
     let listener = TcpListener::bind(&addr).await?;
 
-    start_server(listener, pdf_context).await
+    let options = ServerOptions {
+        diagnostics: resolve_diagnostics_mode(),
+        render_timeout: resolve_render_timeout(),
+        render_limiter: resolve_limit(MAX_CONCURRENT_RENDERS_ENV_VAR).map(RenderLimiter::new),
+        batch_concurrency: resolve_limit(BATCH_CONCURRENCY_ENV_VAR),
+        jobs: resolve_job_options(),
+        readiness: resolve_readiness_check()?,
+    };
+
+    start_server_with_options(listener, context, options).await
+}
+
+/// Interpret an environment variable as a boolean flag.
+fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| matches!(value.trim(), "1" | "true" | "yes"))
+}
+
+/// Determine the default render time limit from the environment.
+fn resolve_render_timeout() -> Option<Duration> {
+    let value = env::var(RENDER_TIMEOUT_ENV_VAR).ok()?;
+    match value.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(millis) => Some(Duration::from_millis(millis)),
+        Err(error) => {
+            tracing::warn!(%error, "Ignoring invalid {RENDER_TIMEOUT_ENV_VAR}");
+            None
+        }
+    }
+}
+
+/// Read a concurrency limit from the environment; unset or `0` means none.
+fn resolve_limit(name: &str) -> Option<NonZeroUsize> {
+    let value = env::var(name).ok()?;
+    match value.trim().parse::<usize>() {
+        Ok(limit) => NonZeroUsize::new(limit),
+        Err(error) => {
+            tracing::warn!(%error, "Ignoring invalid {name}");
+            None
+        }
+    }
+}
+
+/// Determine where job results are kept, and for how long, from the environment.
+fn resolve_job_options() -> JobOptions {
+    let mut options = JobOptions::default();
+    if let Some(directory) = env::var_os(JOB_DIR_ENV_VAR).filter(|value| !value.is_empty()) {
+        options.directory = directory.into();
+    }
+    if let Ok(value) = env::var(JOB_TTL_ENV_VAR) {
+        match value.trim().parse::<u64>() {
+            Ok(secs) => options.ttl = Duration::from_secs(secs),
+            Err(error) => tracing::warn!(%error, "Ignoring invalid {JOB_TTL_ENV_VAR}"),
+        }
+    }
+
+    #[cfg(feature = "webhooks")]
+    {
+        let non_empty = |name| env::var(name).ok().filter(|value| !value.is_empty());
+        options.webhook.secret = non_empty(WEBHOOK_SECRET_ENV_VAR);
+        options.webhook.base_url = non_empty(PUBLIC_URL_ENV_VAR);
+        if let Some(value) = non_empty(WEBHOOK_RETRIES_ENV_VAR) {
+            match value.trim().parse() {
+                Ok(retries) => options.webhook.retries = retries,
+                Err(error) => tracing::warn!(%error, "Ignoring invalid {WEBHOOK_RETRIES_ENV_VAR}"),
+            }
+        }
+        if let Some(value) = non_empty(WEBHOOK_ALLOWED_HOSTS_ENV_VAR) {
+            options.webhook.allowed_hosts = value
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        options.webhook.allow_private = env_flag(WEBHOOK_ALLOW_PRIVATE_ENV_VAR);
+    }
+
+    options
+}
+
+/// Determine the readiness smoke render from the environment.
+///
+/// An invalid input fails startup: probing with a different input than the
+/// one configured would report a readiness nobody asked for.
+fn resolve_readiness_check() -> Result<Option<ReadinessCheck>, AppError> {
+    let Some(template) = env::var(READY_TEMPLATE_ENV_VAR)
+        .ok()
+        .filter(|value| !value.is_empty())
+    else {
+        return Ok(None);
+    };
+    let input =
+        parse_ready_input(env::var(READY_INPUT_ENV_VAR).ok().as_deref()).map_err(|error| {
+            tracing::error!(%error, "Invalid {READY_INPUT_ENV_VAR}");
+            error
+        })?;
+    Ok(Some(ReadinessCheck { template, input }))
+}
+
+/// Parse the smoke render input; unset means `{}`.
+fn parse_ready_input(value: Option<&str>) -> serde_json::Result<serde_json::Value> {
+    value.map_or_else(|| Ok(serde_json::json!({})), serde_json::from_str)
+}
+
+/// Read the shared font directories, separated like `PATH`, from the environment.
+fn resolve_font_dirs() -> Vec<PathBuf> {
+    let Some(value) = env::var_os(FONT_DIRS_ENV_VAR) else {
+        return Vec::new();
+    };
+    env::split_paths(&value)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect()
+}
+
+/// Read the asset cache size in MiB from the environment; setting it at all
+/// loads binary assets lazily.
+fn resolve_lazy_assets() -> Option<usize> {
+    let value = env::var(LAZY_ASSETS_ENV_VAR).ok()?;
+    match value.trim().parse::<usize>() {
+        Ok(megabytes) => Some(megabytes.saturating_mul(1024 * 1024)),
+        Err(error) => {
+            tracing::warn!(%error, "Ignoring invalid {LAZY_ASSETS_ENV_VAR}");
+            None
+        }
+    }
+}
+
+/// Determine the pinned render time from the environment.
+fn resolve_fixed_now() -> Option<DateTime<Utc>> {
+    let value = env::var(FIXED_NOW_ENV_VAR).ok()?;
+    match value.trim().parse() {
+        Ok(now) => Some(now),
+        Err(error) => {
+            tracing::warn!(%error, "Ignoring invalid {FIXED_NOW_ENV_VAR}");
+            None
+        }
+    }
+}
+
+/// Determine whether Typst diagnostics are exposed from the environment.
+fn resolve_diagnostics_mode() -> DiagnosticsMode {
+    let Ok(value) = env::var(DIAGNOSTICS_ENV_VAR) else {
+        return DiagnosticsMode::default();
+    };
+
+    value.parse().unwrap_or_else(|error| {
+        tracing::warn!(%error, "Ignoring invalid {DIAGNOSTICS_ENV_VAR}");
+        DiagnosticsMode::default()
+    })
 }
 
 /// Determine the directory containing Typst assets from CLI args or environment.
@@ -73,6 +276,7 @@ fn resolve_addr(addr_arg: Option<AddrOverride>) -> String {
 #[derive(Debug)]
 struct CliArgs {
     show_version: bool,
+    watch: bool,
     assets_dir: Option<String>,
     addr: Option<AddrOverride>,
     extra: Vec<String>,
@@ -94,6 +298,7 @@ where
     S: Into<String>,
 {
     let mut show_version = false;
+    let mut watch = false;
     let mut assets_dir = None;
     let mut addr = None;
     let mut extra = Vec::new();
@@ -105,6 +310,11 @@ where
             continue;
         }
 
+        if arg == "--watch" || arg == "-w" {
+            watch = true;
+            continue;
+        }
+
         if addr.is_none()
             && let Some(parsed) = parse_addr_arg(&arg)
         {
@@ -122,6 +332,7 @@ where
 
     CliArgs {
         show_version,
+        watch,
         assets_dir,
         addr,
         extra,
@@ -0,0 +1,342 @@
+use std::collections::HashSet;
+
+use lopdf::{Bookmark, Dictionary, Document, Object, ObjectId, StringFormat, dictionary};
+
+use crate::error::AppResult;
+
+/// Concatenate PDFs into one document with a top-level bookmark per input.
+///
+/// Each input is `(title, pdf_bytes)`; its bookmark points at its first page.
+/// Only the page trees and named destinations are carried over:
+/// document-level structures such as outlines, tags and metadata of the
+/// inputs are dropped. Attributes a page inherits from its page tree are
+/// copied onto the page first, and each input's destination names get a
+/// prefix of their own so that internal links keep their targets.
+pub(crate) fn merge_pdfs(documents: &[(String, Vec<u8>)]) -> AppResult<Vec<u8>> {
+    let mut merged = Document::with_version("1.7");
+    let pages_id = merged.new_object_id();
+    let mut kids: Vec<ObjectId> = Vec::new();
+    let mut destinations = Vec::new();
+
+    for (position, (title, pdf_bytes)) in documents.iter().enumerate() {
+        let mut document = Document::load_mem(pdf_bytes)?;
+        document.renumber_objects_with(merged.max_id + 1);
+        merged.max_id = merged.max_id.max(document.max_id);
+
+        let prefix = format!("{position}-");
+        destinations.extend(
+            named_destinations(&document)
+                .into_iter()
+                .map(|(name, destination)| ([prefix.as_bytes(), &name].concat(), destination)),
+        );
+        for object in document.objects.values_mut() {
+            prefix_destination_names(object, prefix.as_bytes());
+        }
+
+        // `get_pages` is keyed by page number, so this keeps the page order.
+        let pages = document.get_pages().into_values().collect::<Vec<_>>();
+        for &page_id in &pages {
+            let inherited = inherited_attributes(&document, page_id);
+            let page = document.get_object_mut(page_id)?.as_dict_mut()?;
+            for (key, value) in inherited {
+                page.set(key, value);
+            }
+        }
+        for (id, object) in document.objects {
+            match object.type_name().unwrap_or_default() {
+                b"Catalog" | b"Pages" | b"Outlines" => {}
+                b"Page" => {
+                    let mut page = object.as_dict()?.clone();
+                    page.set("Parent", pages_id);
+                    merged.objects.insert(id, Object::Dictionary(page));
+                }
+                _ => {
+                    merged.objects.insert(id, object);
+                }
+            }
+        }
+
+        if let Some(&first_page) = pages.first() {
+            merged.add_bookmark(
+                Bookmark::new(title.clone(), [0.0, 0.0, 0.0], 0, first_page),
+                None,
+            );
+        }
+        kids.extend(pages);
+    }
+
+    merged.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+        }),
+    );
+
+    let mut catalog = dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+        "PageMode" => "UseOutlines",
+    };
+    if let Some(outline_id) = merged.build_outline() {
+        catalog.set("Outlines", outline_id);
+    }
+    if !destinations.is_empty() {
+        // Leaves of a name tree are sorted by key.
+        destinations.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let names = destinations
+            .into_iter()
+            .flat_map(|(name, destination)| {
+                [Object::String(name, StringFormat::Literal), destination]
+            })
+            .collect::<Vec<_>>();
+        catalog.set(
+            "Names",
+            dictionary! { "Dests" => dictionary! { "Names" => names } },
+        );
+    }
+    let catalog_id = merged.add_object(catalog);
+    merged.trailer.set("Root", catalog_id);
+
+    // Drop whatever only the discarded catalogs referred to.
+    merged.prune_objects();
+
+    let mut bytes = Vec::new();
+    merged.save_to(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Page attributes that a page without them inherits from its ancestors.
+const INHERITABLE_ATTRIBUTES: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// The inheritable attributes `page_id` lacks and the nearest ancestor in
+/// its page tree sets, which it would lose when moved to another tree.
+fn inherited_attributes(document: &Document, page_id: ObjectId) -> Vec<(&'static [u8], Object)> {
+    let Ok(page) = document.get_dictionary(page_id) else {
+        return Vec::new();
+    };
+    let mut missing = INHERITABLE_ATTRIBUTES
+        .into_iter()
+        .filter(|key| !page.has(key))
+        .collect::<Vec<_>>();
+    let mut inherited = Vec::new();
+    let mut visited = HashSet::from([page_id]);
+    let mut parent = page.get(b"Parent").and_then(Object::as_reference).ok();
+
+    while let Some(id) = parent.filter(|id| visited.insert(*id)) {
+        let Ok(node) = document.get_dictionary(id) else {
+            break;
+        };
+        missing.retain(|&key| match node.get(key) {
+            Ok(value) => {
+                inherited.push((key, value.clone()));
+                false
+            }
+            Err(_) => true,
+        });
+        parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    inherited
+}
+
+/// Every named destination of `document`, from the catalog's `/Names /Dests`
+/// name tree and from the older `/Dests` dictionary.
+fn named_destinations(document: &Document) -> Vec<(Vec<u8>, Object)> {
+    let mut destinations = Vec::new();
+    let Ok(catalog) = document.catalog() else {
+        return destinations;
+    };
+
+    let tree = catalog
+        .get(b"Names")
+        .and_then(|names| document.dereference(names))
+        .and_then(|(_, names)| names.as_dict())
+        .and_then(|names| names.get(b"Dests"));
+    if let Ok(tree) = tree {
+        collect_name_tree(document, tree, &mut destinations, &mut HashSet::new());
+    }
+
+    let dests = catalog
+        .get(b"Dests")
+        .and_then(|dests| document.dereference(dests))
+        .and_then(|(_, dests)| dests.as_dict());
+    if let Ok(dests) = dests {
+        destinations.extend(
+            dests
+                .iter()
+                .map(|(name, destination)| (name.clone(), destination.clone())),
+        );
+    }
+    destinations
+}
+
+/// Collect the entries of the name tree rooted at `node`; nodes already in
+/// `visited` are skipped, so a malformed tree cannot loop.
+fn collect_name_tree(
+    document: &Document,
+    node: &Object,
+    entries: &mut Vec<(Vec<u8>, Object)>,
+    visited: &mut HashSet<ObjectId>,
+) {
+    let Ok((id, node)) = document.dereference(node) else {
+        return;
+    };
+    if id.is_some_and(|id| !visited.insert(id)) {
+        return;
+    }
+    let Ok(node) = node.as_dict() else {
+        return;
+    };
+
+    if let Ok(names) = node.get(b"Names").and_then(Object::as_array) {
+        for pair in names.chunks_exact(2) {
+            if let Ok(name) = pair[0].as_str() {
+                entries.push((name.to_vec(), pair[1].clone()));
+            }
+        }
+    }
+    if let Ok(kids) = node.get(b"Kids").and_then(Object::as_array) {
+        for kid in kids {
+            collect_name_tree(document, kid, entries, visited);
+        }
+    }
+}
+
+/// Prefix the destination names that links and go-to actions in `object`
+/// refer to, matching the renamed entries of the merged name tree.
+fn prefix_destination_names(object: &mut Object, prefix: &[u8]) {
+    let Ok(dict) = object.as_dict_mut() else {
+        return;
+    };
+    prefix_name(dict, b"Dest", prefix);
+    if dict.get(b"S").and_then(Object::as_name).ok() == Some(b"GoTo".as_slice()) {
+        prefix_name(dict, b"D", prefix);
+    }
+    if let Ok(action) = dict.get_mut(b"A") {
+        prefix_destination_names(action, prefix);
+    }
+}
+
+/// Replace a named destination under `key` with its prefixed string form.
+fn prefix_name(dict: &mut Dictionary, key: &[u8], prefix: &[u8]) {
+    let name = match dict.get(key) {
+        Ok(Object::String(name, _) | Object::Name(name)) => [prefix, name].concat(),
+        _ => return,
+    };
+    dict.set(key, Object::String(name, StringFormat::Literal));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use lopdf::{Document, Object, dictionary};
+
+    use super::merge_pdfs;
+    use crate::pdf::PdfContext;
+
+    /// A one-page PDF whose size and resources are only set on the page tree.
+    fn pdf_with_inherited_attributes() -> Vec<u8> {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let resources_id = document.add_object(dictionary! { "ProcSet" => vec!["PDF".into()] });
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+        });
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+                "MediaBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+                "Rotate" => 90,
+                "Resources" => resources_id,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Links to labels keep pointing at the right page of the right input.
+    #[test]
+    fn merge_keeps_internal_links() {
+        crate::logging::init_for_tests();
+        let template = b"= Intro <intro>\n#pagebreak()\nSee #link(<intro>)[the intro].";
+        let context =
+            Arc::new(PdfContext::from_assets(&[("doc.typ", template.as_slice())]).unwrap());
+        let render = || {
+            PdfContext::render_pdf(
+                Arc::clone(&context),
+                "doc.typ".into(),
+                serde_json::json!({}),
+                &Default::default(),
+                &Default::default(),
+            )
+            .unwrap()
+        };
+        let merged = merge_pdfs(&[("a".into(), render()), ("b".into(), render())]).unwrap();
+        let merged = Document::load_mem(&merged).unwrap();
+
+        let names = merged
+            .catalog()
+            .unwrap()
+            .get(b"Names")
+            .and_then(Object::as_dict)
+            .and_then(|names| names.get(b"Dests"))
+            .and_then(Object::as_dict)
+            .and_then(|dests| dests.get(b"Names"))
+            .and_then(Object::as_array)
+            .unwrap();
+        let destinations = names
+            .chunks_exact(2)
+            .map(|pair| (pair[0].as_str().unwrap().to_vec(), pair[1].clone()))
+            .collect::<HashMap<_, _>>();
+
+        // Each input's link leads to the first page of that same input.
+        let pages = merged.get_pages();
+        for (position, link_page) in [(0, 2), (1, 4)] {
+            let link = merged
+                .get_page_annotations(pages[&link_page])
+                .unwrap()
+                .into_iter()
+                .find(|annotation| annotation.has(b"Dest"))
+                .expect("expected a link annotation");
+            let name = link.get(b"Dest").unwrap().as_str().unwrap();
+            assert_eq!(name, format!("{position}-intro").as_bytes());
+
+            let (_, destination) = merged.dereference(&destinations[name]).unwrap();
+            let target = destination.as_array().unwrap()[0].as_reference().unwrap();
+            assert_eq!(target, pages[&(link_page - 1)]);
+        }
+    }
+
+    /// Pages keep the attributes they inherited from their old page tree.
+    #[test]
+    fn merge_keeps_inherited_page_attributes() {
+        let pdf = pdf_with_inherited_attributes();
+        let merged = merge_pdfs(&[("a".into(), pdf.clone()), ("b".into(), pdf)]).unwrap();
+        let merged = Document::load_mem(&merged).unwrap();
+
+        let pages = merged.get_pages();
+        assert_eq!(pages.len(), 2);
+        for page_id in pages.into_values() {
+            let page = merged.get_dictionary(page_id).unwrap();
+            let media_box = page.get(b"MediaBox").unwrap().as_array().unwrap();
+            assert_eq!(media_box[2].as_i64().unwrap(), 200);
+            assert_eq!(page.get(b"Rotate").unwrap().as_i64().unwrap(), 90);
+            let resources = page.get(b"Resources").unwrap().as_reference().unwrap();
+            assert!(merged.get_dictionary(resources).unwrap().has(b"ProcSet"));
+            assert!(!page.has(b"CropBox"));
+        }
+    }
+}
@@ -1,32 +1,63 @@
-use chrono::{Datelike, Timelike};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Serialize;
 use std::{
-    collections::{HashMap, HashSet},
-    path::Path,
-    sync::Arc,
-    time::Instant,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
 };
 use tokio::{io::DuplexStream, task::JoinSet};
-use tokio_util::io::ReaderStream;
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
 use tracing::{debug, info, instrument, trace, warn};
 use typst::{
     Library, LibraryExt, World,
-    diag::{FileError, FileResult},
-    foundations::{Bytes, Datetime, Duration},
-    syntax::{FileId, Source},
+    diag::{FileError, FileResult, PackageError},
+    foundations::{Bytes, Datetime, Dict, Duration as TypstDuration, IntoValue, Smart},
+    layout::{Abs, PageRanges},
+    syntax::{FileId, Source, VirtualRoot, package::PackageSpec},
     text::{Font, FontBook},
-    utils::LazyHash,
+    utils::{LazyHash, Scalar},
 };
-use typst_pdf::{PdfOptions, PdfStandard, PdfStandards, Timestamp};
+use typst_layout::{Page, PagedDocument};
+use typst_pdf::{PdfOptions as TypstPdfOptions, PdfStandard, PdfStandards, Timestamp};
 
 use crate::{
-    assets::{collect_dir_contents, file_id_from_path},
+    assets::{
+        collect_dir_contents, collect_dir_contents_lazily, collect_packages, file_id_from_path,
+        load_font_faces,
+    },
+    catalog::{TemplateInfo, load_catalog},
+    diagnostics::Diagnostic,
     error::{AppError, AppResult},
+    fonts::{FontDescription, FontSlot, FontSource, Fonts},
+    lazy::LazyAssets,
+    limit::RenderLimiter,
+    merge::merge_pdfs,
+    schema::{self, load_schemas},
+    telemetry::{self, RenderMetrics, record_batch},
     zip::ZipResponseWriter,
 };
 
+/// Archive entry listing per-item outcomes in [`BatchErrorMode::Report`].
+pub const BATCH_MANIFEST_NAME: &str = "manifest.json";
+
+/// Gap inserted between pages when merging them into a single SVG.
+const SVG_MERGED_PAGE_GAP_PT: f64 = 10.0;
+
+/// PDF standards enforced when neither the request nor the template picks any.
+const DEFAULT_PDF_STANDARDS: &[PdfStandard] = &[PdfStandard::A_2a];
+
+/// Extension of the sidecar file holding a template's default options,
+/// e.g. `invoice.options.json` next to `invoice.typ`.
+pub(crate) const TEMPLATE_OPTIONS_EXTENSION: &str = "options.json";
+
 /// A single render job inside a batch: which template to render, what file
 /// name to use inside the ZIP, and the JSON payload to inject.
-#[derive(Debug, Clone, serde::Deserialize)]
+#[derive(Debug, Clone, Default, serde::Deserialize)]
 pub struct BatchRenderRequest {
     /// Name of the Typst template to render.
     pub template: String,
@@ -34,6 +65,519 @@ pub struct BatchRenderRequest {
     pub file_name: String,
     /// JSON payload injected into the Typst template.
     pub input: serde_json::Value,
+    /// Per-render settings such as the time limit.
+    #[serde(flatten)]
+    pub options: RenderOptions,
+    /// PDF export settings overriding the template's defaults.
+    #[serde(default)]
+    pub pdf: PdfOptions,
+}
+
+/// What a batch does when one of its items fails to render.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchErrorMode {
+    /// Stop writing the archive at the first failure.
+    #[default]
+    Abort,
+    /// Keep going and list every item's outcome in a `manifest.json` entry.
+    Report,
+}
+
+/// Order in which a batch writes its PDFs into the archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchOrder {
+    /// Write each PDF as soon as it is rendered.
+    #[default]
+    Completion,
+    /// Write PDFs in request order, buffering early finishers.
+    Request,
+}
+
+/// Settings for a whole batch render.
+#[derive(Debug, Clone, Default)]
+pub struct BatchOptions {
+    /// How failed items are handled.
+    pub errors: BatchErrorMode,
+    /// Include resolved Typst diagnostics of failed items in the manifest.
+    pub diagnostics: bool,
+    /// Order of the archive entries.
+    pub order: BatchOrder,
+    /// Maximum renders of this batch in flight at once; defaults to the
+    /// number of available CPUs.
+    pub concurrency: Option<NonZeroUsize>,
+    /// Limit shared with other batches and single renders.
+    pub limiter: Option<RenderLimiter>,
+    /// Counters updated as items finish, for reporting progress elsewhere.
+    pub progress: Option<BatchProgress>,
+}
+
+/// Live counts of finished batch items; clones share the same counters.
+#[derive(Debug, Clone, Default)]
+pub struct BatchProgress {
+    counters: Arc<ProgressCounters>,
+}
+
+#[derive(Debug, Default)]
+struct ProgressCounters {
+    completed: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl BatchProgress {
+    /// Items written to the output so far.
+    pub fn completed(&self) -> usize {
+        self.counters.completed.load(Ordering::Relaxed)
+    }
+
+    /// Items that failed to render so far.
+    pub fn failed(&self) -> usize {
+        self.counters.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// Outcome of a single batch item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchItemStatus {
+    Ok,
+    Error,
+}
+
+/// A `manifest.json` entry describing how one batch item went.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchItemReport {
+    pub file_name: String,
+    pub template: String,
+    pub status: BatchItemStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Font families the template asked for but no font provides.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unknown_fonts: Vec<String>,
+}
+
+impl BatchItemReport {
+    /// Record `error` as the item's outcome.
+    fn fail(&mut self, error: AppError, diagnostics: bool) {
+        self.status = BatchItemStatus::Error;
+        let (message, item_diagnostics) = describe_failure(error, diagnostics);
+        self.error = Some(message);
+        if diagnostics {
+            self.diagnostics = item_diagnostics;
+        }
+    }
+}
+
+/// Split a render error into a short message and the diagnostics behind it,
+/// for reporting failures in a response body rather than as an error status.
+///
+/// Other errors can mention paths and other internals, so they are only
+/// described in full when `detailed` is set, i.e. diagnostics are exposed.
+pub(crate) fn describe_failure(error: AppError, detailed: bool) -> (String, Vec<Diagnostic>) {
+    let message = failure_message(&error, detailed);
+    match error {
+        AppError::TypstCompilation(items) | AppError::PdfExport(items) => (message, items),
+        _ => (message, Vec::new()),
+    }
+}
+
+/// The message [`describe_failure`] reports for `error`.
+pub(crate) fn failure_message(error: &AppError, detailed: bool) -> String {
+    match error {
+        AppError::TypstCompilation(_) => "Typst compilation failed".to_owned(),
+        AppError::PdfExport(_) => "PDF export failed".to_owned(),
+        error if detailed => error.to_string(),
+        error => error.public_message().to_owned(),
+    }
+}
+
+/// Contents of the `manifest.json` entry written in [`BatchErrorMode::Report`].
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchManifest {
+    /// One report per requested item, in request order.
+    pub items: Vec<BatchItemReport>,
+}
+
+/// Settings that apply to a single render regardless of the output format.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct RenderOptions {
+    /// Abort the render once it has been running for this long. In JSON this
+    /// is given in milliseconds as `timeout_ms`.
+    #[serde(
+        default,
+        rename = "timeout_ms",
+        deserialize_with = "deserialize_millis"
+    )]
+    pub timeout: Option<Duration>,
+    /// When the time limit starts counting; `None` starts it with the render.
+    /// Batches and the server set it before waiting for a render slot, so
+    /// the limit covers the wait too.
+    #[serde(skip)]
+    pub timeout_start: Option<Instant>,
+    /// Abort the render once this token is cancelled, e.g. because the client
+    /// that asked for it disconnected.
+    #[serde(skip)]
+    pub cancellation: Option<CancellationToken>,
+    /// String key/value pairs exposed to the template as `sys.inputs`, like
+    /// the Typst CLI's `--input key=value`.
+    #[serde(default)]
+    pub inputs: BTreeMap<String, String>,
+    /// Pin the date and time seen by the template and stamped into the PDF,
+    /// for reproducible output; an RFC 3339 string in JSON.
+    #[serde(default)]
+    pub now: Option<DateTime<Utc>>,
+    /// Collect the compile warnings of the render here, e.g. to report
+    /// unknown font families alongside the output.
+    #[serde(skip)]
+    pub warnings: Option<RenderWarnings>,
+}
+
+/// Prefix of the warning Typst emits for a font family no font provides.
+const UNKNOWN_FONT_WARNING: &str = "unknown font family: ";
+
+/// Compile warnings collected from the renders it is passed to; clones share
+/// the same list.
+#[derive(Debug, Clone, Default)]
+pub struct RenderWarnings {
+    diagnostics: Arc<Mutex<Vec<Diagnostic>>>,
+}
+
+impl RenderWarnings {
+    /// Every warning collected so far.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .clone()
+    }
+
+    /// The font families the templates asked for but no font provides,
+    /// sorted and without duplicates.
+    pub fn unknown_font_families(&self) -> Vec<String> {
+        let diagnostics = self
+            .diagnostics
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let families = diagnostics
+            .iter()
+            .filter_map(|diagnostic| diagnostic.message.strip_prefix(UNKNOWN_FONT_WARNING))
+            .map(str::to_owned)
+            .collect::<BTreeSet<_>>();
+        families.into_iter().collect()
+    }
+
+    fn extend(&self, warnings: Vec<Diagnostic>) {
+        self.diagnostics
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .extend(warnings);
+    }
+}
+
+/// Deserialize an optional millisecond count into a [`Duration`].
+fn deserialize_millis<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let millis: Option<u64> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(millis.map(Duration::from_millis))
+}
+
+/// Cooperative abort state for a single render.
+///
+/// Typst cannot be interrupted from the outside, so the render checks this
+/// between pipeline stages and whenever the compiler asks the [`World`] for
+/// a file, which makes most runaway compiles fail soon after their deadline.
+struct Interrupt {
+    deadline: Option<(Instant, Duration)>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl Interrupt {
+    /// Start the clock for the limits in `options`.
+    fn new(options: &RenderOptions) -> Self {
+        Self {
+            deadline: options.timeout.map(|timeout| {
+                let start = options.timeout_start.unwrap_or_else(Instant::now);
+                (start + timeout, timeout)
+            }),
+            cancellation: options.cancellation.clone(),
+        }
+    }
+
+    /// Fail if the render was cancelled or ran past its deadline.
+    fn check(&self) -> AppResult<()> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(AppError::RenderCancelled);
+        }
+
+        match self.deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => {
+                Err(AppError::RenderTimeout(timeout))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Translate an interruption into a file error the compiler will surface.
+    fn check_file(&self) -> FileResult<()> {
+        self.check()
+            .map_err(|error| FileError::Other(Some(error.to_string().into())))
+    }
+}
+
+/// Which pages of a compiled document to export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageSelection {
+    /// Export every page of the document.
+    #[default]
+    All,
+    /// Export only the page with the given 1-based number.
+    Single(usize),
+}
+
+impl PageSelection {
+    /// Resolve the selection against a compiled document.
+    fn select(self, document: &PagedDocument) -> AppResult<&[Page]> {
+        let pages = document.pages();
+        match self {
+            PageSelection::All => Ok(pages),
+            PageSelection::Single(number) => number
+                .checked_sub(1)
+                .and_then(|index| pages.get(index..=index))
+                .ok_or(AppError::PageOutOfRange {
+                    page: number,
+                    page_count: pages.len(),
+                }),
+        }
+    }
+}
+
+/// Settings for raster (PNG) export.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PngOptions {
+    /// Number of pixels per typographic point; `2.0` yields 144 DPI images.
+    pub pixel_per_pt: f32,
+    /// Pages to render; each selected page becomes a separate PNG.
+    pub pages: PageSelection,
+}
+
+impl PngOptions {
+    /// Largest accepted `pixel_per_pt` (720 DPI); the pixel buffer grows with
+    /// its square, so higher values could exhaust memory on a single page.
+    pub const MAX_PIXEL_PER_PT: f32 = 10.0;
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        Self {
+            pixel_per_pt: 2.0,
+            pages: PageSelection::All,
+        }
+    }
+}
+
+/// Settings for vector (SVG) export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SvgOptions {
+    /// Pages to export.
+    pub pages: PageSelection,
+    /// Combine all selected pages into a single SVG stacked vertically,
+    /// instead of producing one SVG per page.
+    pub merge_pages: bool,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            pages: PageSelection::All,
+            merge_pages: true,
+        }
+    }
+}
+
+/// Which creation timestamp to write into the PDF metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PdfTimestamp {
+    /// The time of the render, or the pinned time if one is set.
+    #[default]
+    Now,
+    /// Leave the creation date out.
+    #[serde(rename = "none")]
+    Omit,
+    /// A fixed point in time; an RFC 3339 string in JSON.
+    #[serde(untagged)]
+    At(DateTime<Utc>),
+}
+
+/// Settings for PDF export.
+///
+/// Every field is optional: unset fields fall back to the template's defaults
+/// from its `.options.json` sidecar, then to PDF/A-2a stamped with the
+/// current time.
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct PdfOptions {
+    /// PDF standards to enforce, e.g. `["a-3b"]`, `["1.7"]` or `["ua-1"]`.
+    pub standards: Option<Vec<PdfStandard>>,
+    /// Stable identifier from which the PDF document ID is derived.
+    pub identifier: Option<String>,
+    /// Creation timestamp written into the metadata.
+    pub timestamp: Option<PdfTimestamp>,
+    /// 1-based page ranges to export, like `1-3,5,8-`.
+    pub page_ranges: Option<String>,
+}
+
+impl PdfOptions {
+    /// Fill every unset field from `defaults`.
+    pub fn with_defaults(&self, defaults: &PdfOptions) -> PdfOptions {
+        PdfOptions {
+            standards: self
+                .standards
+                .clone()
+                .or_else(|| defaults.standards.clone()),
+            identifier: self
+                .identifier
+                .clone()
+                .or_else(|| defaults.identifier.clone()),
+            timestamp: self.timestamp.or(defaults.timestamp),
+            page_ranges: self
+                .page_ranges
+                .clone()
+                .or_else(|| defaults.page_ranges.clone()),
+        }
+    }
+
+    /// Translate into Typst's export options, validating the standards and
+    /// page ranges. `now` is the pinned render time, if any.
+    fn to_typst(&self, now: Option<DateTime<Utc>>) -> AppResult<TypstPdfOptions> {
+        let standards = self.standards.as_deref().unwrap_or(DEFAULT_PDF_STANDARDS);
+        let standards = PdfStandards::new(standards).map_err(|error| {
+            AppError::InvalidRenderOptions(format!(
+                "unsupported PDF standards: {}",
+                error.message()
+            ))
+        })?;
+
+        let timestamp = match self.timestamp.unwrap_or_default() {
+            PdfTimestamp::Now => Some(utc_timestamp(now.unwrap_or_else(Utc::now))?),
+            PdfTimestamp::Omit => None,
+            PdfTimestamp::At(datetime) => Some(utc_timestamp(datetime)?),
+        };
+
+        let page_ranges = self
+            .page_ranges
+            .as_deref()
+            .map(parse_page_ranges)
+            .transpose()?;
+
+        Ok(TypstPdfOptions {
+            ident: match &self.identifier {
+                Some(identifier) => Smart::Custom(identifier.clone()),
+                None => Smart::Auto,
+            },
+            timestamp,
+            // Like the Typst CLI: a partial export cannot be tagged, so tags
+            // are dropped unless a standard (e.g. PDF/A-2a) requires them.
+            tagged: page_ranges.is_none(),
+            page_ranges,
+            standards,
+            ..Default::default()
+        })
+    }
+}
+
+/// Convert a UTC date and time into a PDF timestamp.
+fn utc_timestamp(datetime: DateTime<Utc>) -> AppResult<Timestamp> {
+    let datetime = Datetime::from_ymd_hms(
+        datetime.year(),
+        datetime.month() as u8,
+        datetime.day() as u8,
+        datetime.hour() as u8,
+        datetime.minute() as u8,
+        datetime.second() as u8,
+    )
+    .ok_or_else(|| {
+        AppError::InvalidRenderOptions(format!("timestamp {datetime} cannot be stored in a PDF"))
+    })?;
+    Ok(Timestamp::new_utc(datetime))
+}
+
+/// Parse comma-separated 1-based page ranges such as `1-3,5,8-`.
+fn parse_page_ranges(ranges: &str) -> AppResult<PageRanges> {
+    let invalid = || AppError::InvalidRenderOptions(format!("invalid page ranges `{ranges}`"));
+    let page = |number: &str| -> AppResult<Option<NonZeroUsize>> {
+        match number.trim() {
+            "" => Ok(None),
+            number => number.parse().map(Some).map_err(|_| invalid()),
+        }
+    };
+
+    ranges
+        .split(',')
+        .map(|range| match range.split_once('-') {
+            Some((start, end)) => Ok(page(start)?..=page(end)?),
+            None => match page(range)? {
+                Some(number) => Ok(Some(number)..=Some(number)),
+                None => Err(invalid()),
+            },
+        })
+        .collect::<AppResult<Vec<_>>>()
+        .map(PageRanges::new)
+}
+
+/// Per-template defaults read from a template's `.options.json` sidecar.
+#[derive(Debug, Default, serde::Deserialize)]
+struct TemplateOptions {
+    #[serde(default)]
+    pdf: PdfOptions,
+}
+
+/// Settings applied when loading a [`PdfContext`] from disk.
+#[derive(Debug, Clone)]
+pub struct ContextOptions {
+    /// Local package directory laid out like Typst's package cache
+    /// (`{namespace}/{name}/{version}/`). Packages are never downloaded.
+    pub packages: Option<PathBuf>,
+    /// Pin the current date and time for every render, unless a request
+    /// pins its own; makes output reproducible.
+    pub now: Option<DateTime<Utc>>,
+    /// Shared font directories, searched recursively. Fonts are read when a
+    /// render first uses them; a family in the assets directory hides the
+    /// same family here, as does an earlier directory for a later one.
+    pub font_dirs: Vec<PathBuf>,
+    /// Also index the operating system's font directories, after `font_dirs`.
+    pub system_fonts: bool,
+    /// Fall back to the fonts embedded in the binary for families no other
+    /// font provides. Defaults to `true`; without the `embedded-fonts`
+    /// feature there are no such fonts and this is ignored.
+    pub embedded_fonts: bool,
+    /// Leave binary assets such as images on disk and read them when a render
+    /// first uses them, caching up to this many bytes. Sources, fonts, and
+    /// template sidecars are still loaded up front.
+    pub lazy_assets: Option<usize>,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            packages: None,
+            now: None,
+            font_dirs: Vec::new(),
+            system_fonts: false,
+            embedded_fonts: true,
+            lazy_assets: None,
+        }
+    }
 }
 
 /// Shared Typst compilation state used when rendering PDFs.
@@ -42,7 +586,20 @@ pub struct PdfContext {
     library: LazyHash<Library>,
     fontbook: LazyHash<FontBook>,
     assets: HashMap<FileId, Bytes>,
-    fonts: Vec<Font>,
+    /// Binary assets read on first use, when loaded lazily.
+    lazy_assets: Option<LazyAssets>,
+    fonts: Vec<FontSlot>,
+    /// Descriptions of `fonts`, built on first use.
+    font_descriptions: OnceLock<Vec<FontDescription>>,
+    packages: HashSet<PackageSpec>,
+    /// Default PDF options per template name.
+    pdf_defaults: HashMap<String, PdfOptions>,
+    /// Description of every template, sorted by path.
+    catalog: Vec<TemplateInfo>,
+    /// Compiled input schema per template file.
+    schemas: HashMap<FileId, jsonschema::Validator>,
+    /// Pinned date and time for every render, if any.
+    now: Option<DateTime<Utc>>,
 }
 
 /// Wrapper implementing Typst's [`World`] trait for a single render invocation.
@@ -50,6 +607,14 @@ struct RenderInput {
     context: Arc<PdfContext>,
     main_source: Source,
     input_data: (FileId, Bytes),
+    interrupt: Interrupt,
+    /// Library carrying this render's `sys.inputs`; `None` means the
+    /// context's shared library is used.
+    library: Option<LazyHash<Library>>,
+    /// Pinned date and time; `None` means the wall clock.
+    now: Option<DateTime<Utc>>,
+    /// Where compile warnings are reported, besides the log.
+    warnings: Option<RenderWarnings>,
 }
 
 impl RenderInput {
@@ -58,33 +623,99 @@ impl RenderInput {
         context: Arc<PdfContext>,
         source_name: String,
         input: serde_json::Value,
+        options: &RenderOptions,
     ) -> AppResult<Self> {
         trace!(template = %source_name, "Preparing render input");
+        let interrupt = Interrupt::new(options);
+        let now = options.now.or(context.now);
         // Find the main source by name
         let main_source = context
-            .sources
-            .iter()
+            .templates()
             .find(|s| s.id().vpath().file_name() == Some(source_name.as_str()))
             .cloned()
             .ok_or_else(|| AppError::MainSourceNotFound(source_name.clone()))?;
         trace!(template = %source_name, source_id = ?main_source.id(), "Resolved template source");
 
+        // Reject input the template does not expect before Typst trips over it
+        if let Some(validator) = context.schemas.get(&main_source.id()) {
+            schema::validate(validator, &input)?;
+        }
+
         // Prepare the input data as a virtual file
         let input_bytes = Bytes::new(serde_json::to_vec(&input)?);
         let input_file_id = file_id_from_path(Path::new("input.json"))?;
         trace!(file_id = ?input_file_id, "Encoded render input as virtual file");
 
+        // Only build a dedicated library when there are inputs, so plain
+        // renders keep sharing the context's (already hashed) one.
+        let library = (!options.inputs.is_empty()).then(|| {
+            let inputs = options
+                .inputs
+                .iter()
+                .map(|(key, value)| (key.as_str().into(), value.as_str().into_value()))
+                .collect::<Dict>();
+            trace!(count = inputs.len(), "Building library with sys.inputs");
+            LazyHash::new(Library::builder().with_inputs(inputs).build())
+        });
+
         Ok(RenderInput {
             context,
             main_source,
             input_data: (input_file_id, input_bytes),
+            interrupt,
+            library,
+            now,
+            warnings: options.warnings.clone(),
         })
     }
 }
 
+/// Read the `.options.json` sidecars of all templates, keyed by template name.
+fn load_template_defaults(
+    sources: &[Source],
+    assets: &HashMap<FileId, Bytes>,
+) -> AppResult<HashMap<String, PdfOptions>> {
+    let mut defaults = HashMap::new();
+    for source in sources {
+        let id = source.id();
+        if !matches!(id.root(), VirtualRoot::Project) {
+            continue;
+        }
+        let vpath = id.vpath();
+        let Some(name) = vpath.file_name() else {
+            continue;
+        };
+
+        let sidecar =
+            Path::new(vpath.get_without_slash()).with_extension(TEMPLATE_OPTIONS_EXTENSION);
+        let Some(bytes) = assets.get(&file_id_from_path(&sidecar)?) else {
+            continue;
+        };
+        let options: TemplateOptions =
+            serde_json::from_slice(bytes.as_slice()).map_err(|source| {
+                AppError::TemplateOptions {
+                    file: sidecar.display().to_string(),
+                    source,
+                }
+            })?;
+        debug!(template = name, ?options, "Loaded template options");
+        defaults.insert(name.to_owned(), options.pdf);
+    }
+    Ok(defaults)
+}
+
 impl PdfContext {
     /// Load all Typst sources, assets, and fonts from a directory tree into memory.
     pub fn from_directory(path: impl AsRef<Path>) -> AppResult<PdfContext> {
+        Self::from_directory_with_options(path, &ContextOptions::default())
+    }
+
+    /// Like [`PdfContext::from_directory`], but also loads the packages and
+    /// other resources configured in `options`.
+    pub fn from_directory_with_options(
+        path: impl AsRef<Path>,
+        options: &ContextOptions,
+    ) -> AppResult<PdfContext> {
         let path = path.as_ref();
         let absolute_path =
             std::fs::canonicalize(path).map_err(|source| AppError::CanonicalizePath {
@@ -98,35 +729,73 @@ impl PdfContext {
             return Err(AppError::NotADirectory(absolute_path.display().to_string()));
         }
 
-        let assets = collect_dir_contents(absolute_path)?;
+        let (mut assets, lazy_assets) = match options.lazy_assets {
+            Some(capacity) => {
+                let mut assets = collect_dir_contents_lazily(&absolute_path)?;
+                let files = std::mem::take(&mut assets.deferred);
+                (
+                    assets,
+                    Some(LazyAssets::new(absolute_path, files, capacity)),
+                )
+            }
+            None => (collect_dir_contents(&absolute_path)?, None),
+        };
+        if let Some(packages) = &options.packages {
+            info!("Loading packages from directory: {}", packages.display());
+            if !packages.is_dir() {
+                return Err(AppError::NotADirectory(packages.display().to_string()));
+            }
+            assets.merge(collect_packages(packages)?);
+        }
         debug!(
             sources = assets.sources.len(),
             fonts = assets.fonts.len(),
             binaries = assets.assets.len(),
+            packages = assets.packages.len(),
             "Collected assets from disk"
         );
 
-        let mut fontbook = FontBook::new();
-        for font in &assets.fonts {
-            fontbook.push(font.info().clone());
+        let mut fonts = Fonts::new(assets.fonts);
+        for dir in &options.font_dirs {
+            info!("Indexing fonts in directory: {}", dir.display());
+            fonts.add_dir(dir, FontSource::Shared)?;
+        }
+        if options.system_fonts {
+            info!("Indexing system fonts");
+            fonts.add_system();
+        }
+        if options.embedded_fonts {
+            fonts.add_embedded();
         }
 
+        let pdf_defaults = load_template_defaults(&assets.sources, &assets.assets)?;
+        let catalog = load_catalog(&assets.sources, &assets.assets, &assets.modified)?;
+        let schemas = load_schemas(&assets.sources, &assets.assets)?;
         Ok(PdfContext {
+            pdf_defaults,
+            catalog,
+            schemas,
+            now: options.now,
             sources: assets.sources,
             library: LazyHash::new(Library::default()),
-            fontbook: LazyHash::new(fontbook),
+            fontbook: LazyHash::new(fonts.book),
             assets: assets.assets,
-            fonts: assets.fonts,
+            lazy_assets,
+            fonts: fonts.slots,
+            font_descriptions: OnceLock::new(),
+            packages: assets.packages,
         })
     }
 
     /// Build a context from in-memory assets provided as (filename, contents) tuples.
+    ///
+    /// With the `embedded-fonts` feature, the embedded fonts are added as a
+    /// fallback like with the default [`ContextOptions`].
     pub fn from_assets(assets: &[(&str, &[u8])]) -> AppResult<PdfContext> {
         info!(count = assets.len(), "Loading assets from memory");
         let mut sources = Vec::new();
         let mut binaries = HashMap::new();
         let mut fonts = Vec::new();
-        let mut fontbook = FontBook::new();
 
         for (name, contents) in assets {
             let path = Path::new(name);
@@ -143,11 +812,10 @@ impl PdfContext {
                     })?;
                     sources.push(Source::new(file_id, content));
                 }
-                Some("ttf") | Some("otf") | Some("woff") | Some("woff2") => {
-                    if let Some(font) = Font::new(Bytes::new(contents.to_vec()), 0) {
-                        fontbook.push(font.info().clone());
-                        fonts.push(font);
-                    }
+                Some("ttf") | Some("otf") | Some("ttc") | Some("otc") | Some("woff")
+                | Some("woff2") => {
+                    let faces = load_font_faces(Bytes::new(contents.to_vec()), path);
+                    fonts.extend(faces.into_iter().map(|font| (path.to_path_buf(), font)));
                 }
                 _ => {
                     binaries.insert(file_id, Bytes::new(contents.to_vec()));
@@ -155,51 +823,89 @@ impl PdfContext {
             }
         }
 
+        let mut fonts = Fonts::new(fonts);
+        fonts.add_embedded();
         Ok(PdfContext {
+            pdf_defaults: load_template_defaults(&sources, &binaries)?,
+            catalog: load_catalog(&sources, &binaries, &HashMap::new())?,
+            schemas: load_schemas(&sources, &binaries)?,
+            now: None,
             sources,
             library: LazyHash::new(Library::default()),
-            fontbook: LazyHash::new(fontbook),
+            fontbook: LazyHash::new(fonts.book),
             assets: binaries,
-            fonts,
+            lazy_assets: None,
+            fonts: fonts.slots,
+            font_descriptions: OnceLock::new(),
+            packages: HashSet::new(),
         })
     }
 
-    /// Check whether a template with the provided name exists in the context.
-    pub fn has_template(&self, source_name: &str) -> bool {
+    /// Iterate over the project sources that can be rendered as templates,
+    /// leaving out files that belong to packages.
+    fn templates(&self) -> impl Iterator<Item = &Source> {
         self.sources
             .iter()
+            .filter(|source| matches!(source.id().root(), VirtualRoot::Project))
+    }
+
+    /// Check whether a template with the provided name exists in the context.
+    pub fn has_template(&self, source_name: &str) -> bool {
+        self.templates()
             .any(|source| source.id().vpath().file_name() == Some(source_name))
     }
 
     /// Return the available template file names in stable sorted order.
     pub fn template_names(&self) -> Vec<String> {
         let mut templates = self
-            .sources
-            .iter()
+            .templates()
             .filter_map(|source| source.id().vpath().file_name().map(str::to_owned))
             .collect::<Vec<_>>();
         templates.sort();
         templates
     }
 
-    /// Render a Typst template with the provided JSON payload into PDF bytes.
-    pub fn render(
-        context: Arc<Self>,
-        source_name: String,
-        input: serde_json::Value,
-    ) -> AppResult<Vec<u8>> {
-        trace!(template = %source_name, "Starting render pipeline");
-        let render_input: RenderInput = RenderInput::new(context, source_name, input)?;
+    /// Describe every template with its path, size, content hash, and the
+    /// metadata from its sidecar files, sorted by path.
+    pub fn catalog(&self) -> &[TemplateInfo] {
+        &self.catalog
+    }
+
+    /// Describe every font face available to templates, in the order Typst
+    /// prefers them. Shared and system fonts are described without being
+    /// loaded.
+    ///
+    /// The descriptions are built on first call and kept with the context;
+    /// with many system fonts the first call can take a moment.
+    pub fn fonts(&self) -> &[FontDescription] {
+        self.font_descriptions.get_or_init(|| {
+            self.fonts
+                .iter()
+                .enumerate()
+                .filter_map(|(index, slot)| Some(slot.describe(self.fontbook.info(index)?)))
+                .collect()
+        })
+    }
+
+    /// Compile the render input into a paged document, forwarding any compile
+    /// warnings to the log.
+    fn compile(render_input: &RenderInput, metrics: &RenderMetrics) -> AppResult<PagedDocument> {
+        trace!(source_id = ?render_input.main_source.id(), "Starting render pipeline");
 
         let compile_start = Instant::now();
-        let result = typst::compile(&render_input);
-        let document = result
-            .output
-            .map_err(|errors| AppError::TypstCompilation(errors.into_iter().collect()))?;
+        let result = typst::compile(render_input);
+        // An interrupted compile usually fails with a confusing file error, so
+        // report the interruption itself instead.
+        render_input.interrupt.check()?;
+        let document = result.output.map_err(|errors| {
+            AppError::TypstCompilation(Diagnostic::resolve_all(render_input, &errors))
+        })?;
 
+        let compile_time = compile_start.elapsed();
+        metrics.compiled(compile_time);
         info!(
             "Compile took {} ms, {} warnings",
-            compile_start.elapsed().as_millis(),
+            compile_time.as_millis(),
             result.warnings.len()
         );
 
@@ -208,36 +914,91 @@ impl PdfContext {
             trace!(?warning, "Forwarded compile warning");
         });
 
-        let pdf_gen_start = Instant::now();
-        let now = chrono::Utc::now();
-
-        let timestamp = Timestamp::new_utc(
-            Datetime::from_ymd_hms(
-                now.year(),
-                now.month() as u8,
-                now.day() as u8,
-                now.hour() as u8,
-                now.minute() as u8,
-                now.second() as u8,
-            )
-            .expect("current UTC datetime should be valid"),
-        );
+        let warnings = Diagnostic::resolve_all(render_input, &result.warnings);
+        warnings
+            .iter()
+            .filter(|warning| warning.message.starts_with(UNKNOWN_FONT_WARNING))
+            .for_each(|_| metrics.unknown_font());
+        if let Some(sink) = &render_input.warnings {
+            sink.extend(warnings);
+        }
 
-        let pdf_bytes = typst_pdf::pdf(
-            &document,
-            &PdfOptions {
-                timestamp: Some(timestamp),
-                standards: PdfStandards::new(&[PdfStandard::A_2a])
-                    .expect("PDF standards should be valid"),
-                ..Default::default()
-            },
-        )
-        .map_err(|errors| AppError::PdfExport(errors.into_iter().collect()))?;
+        Ok(document)
+    }
 
-        debug!(
-            "PDF generation took {} ms",
-            pdf_gen_start.elapsed().as_millis()
+    /// Render a Typst template with the provided JSON payload into PDF bytes.
+    pub fn render(
+        context: Arc<Self>,
+        source_name: String,
+        input: serde_json::Value,
+    ) -> AppResult<Vec<u8>> {
+        Self::render_with_options(context, source_name, input, &RenderOptions::default())
+    }
+
+    /// Render a Typst template into PDF bytes, honouring the time limit and
+    /// cancellation in `options`.
+    pub fn render_with_options(
+        context: Arc<Self>,
+        source_name: String,
+        input: serde_json::Value,
+        options: &RenderOptions,
+    ) -> AppResult<Vec<u8>> {
+        Self::render_pdf(context, source_name, input, &PdfOptions::default(), options)
+    }
+
+    /// Render a Typst template into PDF bytes with explicit export settings,
+    /// which override the template's defaults field by field.
+    pub fn render_pdf(
+        context: Arc<Self>,
+        source_name: String,
+        input: serde_json::Value,
+        pdf_options: &PdfOptions,
+        render_options: &RenderOptions,
+    ) -> AppResult<Vec<u8>> {
+        let metrics = RenderMetrics::start(&context, &source_name, "pdf");
+        let result = Self::export_pdf(
+            context,
+            source_name,
+            input,
+            pdf_options,
+            render_options,
+            &metrics,
         );
+        metrics.finish(result)
+    }
+
+    fn export_pdf(
+        context: Arc<Self>,
+        source_name: String,
+        input: serde_json::Value,
+        pdf_options: &PdfOptions,
+        render_options: &RenderOptions,
+        metrics: &RenderMetrics,
+    ) -> AppResult<Vec<u8>> {
+        let mut pdf_options = match context.pdf_defaults.get(&source_name) {
+            Some(defaults) => pdf_options.with_defaults(defaults),
+            None => pdf_options.clone(),
+        };
+        // A pinned clock asks for reproducible output, so fall back to an
+        // identifier derived from the template and input (Typst only embeds
+        // its hash).
+        let now = render_options.now.or(context.now);
+        if now.is_some() && pdf_options.identifier.is_none() {
+            pdf_options.identifier = Some(format!("{source_name}\n{input}"));
+        }
+        let typst_options = pdf_options.to_typst(now)?;
+
+        let render_input = RenderInput::new(context, source_name, input, render_options)?;
+        let document = Self::compile(&render_input, metrics)?;
+
+        let pdf_gen_start = Instant::now();
+        let pdf_bytes = typst_pdf::pdf(&document, &typst_options).map_err(|errors| {
+            AppError::PdfExport(Diagnostic::resolve_all(&render_input, &errors))
+        })?;
+
+        let pdf_gen_time = pdf_gen_start.elapsed();
+        metrics.exported(pdf_gen_time, pdf_bytes.len());
+        debug!("PDF generation took {} ms", pdf_gen_time.as_millis());
 
         // Typst caches compilation results via comemo. Each render produces a
         // fresh PDF from a unique JSON payload, so the cache cannot help and
@@ -247,8 +1008,170 @@ impl PdfContext {
         Ok(pdf_bytes)
     }
 
+    /// Render a Typst template with the provided JSON payload into one PNG
+    /// image per selected page.
+    pub fn render_png(
+        context: Arc<Self>,
+        source_name: String,
+        input: serde_json::Value,
+        options: &PngOptions,
+        render_options: &RenderOptions,
+    ) -> AppResult<Vec<Vec<u8>>> {
+        let metrics = RenderMetrics::start(&context, &source_name, "png");
+        let result = Self::export_png(
+            context,
+            source_name,
+            input,
+            options,
+            render_options,
+            &metrics,
+        );
+        metrics.finish(result)
+    }
+
+    fn export_png(
+        context: Arc<Self>,
+        source_name: String,
+        input: serde_json::Value,
+        options: &PngOptions,
+        render_options: &RenderOptions,
+        metrics: &RenderMetrics,
+    ) -> AppResult<Vec<Vec<u8>>> {
+        if !options.pixel_per_pt.is_finite() || options.pixel_per_pt <= 0.0 {
+            return Err(AppError::InvalidRenderOptions(format!(
+                "pixel_per_pt must be a positive number, got {}",
+                options.pixel_per_pt
+            )));
+        }
+        if options.pixel_per_pt > PngOptions::MAX_PIXEL_PER_PT {
+            return Err(AppError::InvalidRenderOptions(format!(
+                "pixel_per_pt must be at most {}, got {}",
+                PngOptions::MAX_PIXEL_PER_PT,
+                options.pixel_per_pt
+            )));
+        }
+
+        let render_input = RenderInput::new(context, source_name, input, render_options)?;
+        let document = Self::compile(&render_input, metrics)?;
+        let pages = options.pages.select(&document)?;
+
+        let png_gen_start = Instant::now();
+        let raster_options = typst_render::RenderOptions {
+            pixel_per_pt: Scalar::new(f64::from(options.pixel_per_pt)),
+            ..Default::default()
+        };
+
+        let images = pages
+            .iter()
+            .map(|page| {
+                render_input.interrupt.check()?;
+                typst_render::render(page, &raster_options)
+                    .encode_png()
+                    .map_err(|error| AppError::PngExport(error.to_string()))
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
+        let png_gen_time = png_gen_start.elapsed();
+        metrics.exported(png_gen_time, images.iter().map(Vec::len).sum());
+        debug!(
+            pages = images.len(),
+            "PNG generation took {} ms",
+            png_gen_time.as_millis()
+        );
+
+        // See `render`: the comemo cache cannot be reused across payloads.
+        comemo::evict(0);
+
+        Ok(images)
+    }
+
+    /// Render a Typst template with the provided JSON payload into SVG
+    /// documents: one per selected page, or a single merged document when
+    /// [`SvgOptions::merge_pages`] is set.
+    pub fn render_svg(
+        context: Arc<Self>,
+        source_name: String,
+        input: serde_json::Value,
+        options: &SvgOptions,
+        render_options: &RenderOptions,
+    ) -> AppResult<Vec<String>> {
+        let metrics = RenderMetrics::start(&context, &source_name, "svg");
+        let result = Self::export_svg(
+            context,
+            source_name,
+            input,
+            options,
+            render_options,
+            &metrics,
+        );
+        metrics.finish(result)
+    }
+
+    fn export_svg(
+        context: Arc<Self>,
+        source_name: String,
+        input: serde_json::Value,
+        options: &SvgOptions,
+        render_options: &RenderOptions,
+        metrics: &RenderMetrics,
+    ) -> AppResult<Vec<String>> {
+        let render_input = RenderInput::new(context, source_name, input, render_options)?;
+        let document = Self::compile(&render_input, metrics)?;
+        let pages = options.pages.select(&document)?;
+
+        let svg_gen_start = Instant::now();
+        let svg_options = typst_svg::SvgOptions::default();
+        let svgs = match (options.merge_pages, options.pages) {
+            (true, PageSelection::All) => vec![typst_svg::svg_merged(
+                &document,
+                &svg_options,
+                Abs::pt(SVG_MERGED_PAGE_GAP_PT),
+            )],
+            _ => pages
+                .iter()
+                .map(|page| {
+                    render_input.interrupt.check()?;
+                    Ok(typst_svg::svg(page, &svg_options))
+                })
+                .collect::<AppResult<_>>()?,
+        };
+
+        let svg_gen_time = svg_gen_start.elapsed();
+        metrics.exported(svg_gen_time, svgs.iter().map(String::len).sum());
+        debug!(
+            documents = svgs.len(),
+            "SVG generation took {} ms",
+            svg_gen_time.as_millis()
+        );
+
+        // See `render`: the comemo cache cannot be reused across payloads.
+        comemo::evict(0);
+
+        Ok(svgs)
+    }
+
     /// Validate that every request in the batch references a known template.
     pub fn validate_batch(&self, requests: &[BatchRenderRequest]) -> AppResult<()> {
+        self.validate_batch_with_options(requests, &BatchOptions::default())
+    }
+
+    /// Like [`PdfContext::validate_batch`], additionally rejecting items that
+    /// would collide with the manifest written in [`BatchErrorMode::Report`].
+    pub fn validate_batch_with_options(
+        &self,
+        requests: &[BatchRenderRequest],
+        options: &BatchOptions,
+    ) -> AppResult<()> {
+        if options.errors == BatchErrorMode::Report
+            && requests
+                .iter()
+                .any(|request| request.file_name == BATCH_MANIFEST_NAME)
+        {
+            return Err(AppError::InvalidRenderOptions(format!(
+                "`{BATCH_MANIFEST_NAME}` is reserved for the batch manifest"
+            )));
+        }
+
         let mut checked = HashSet::new();
         for request in requests {
             if checked.insert(request.template.as_str()) && !self.has_template(&request.template) {
@@ -260,39 +1183,174 @@ impl PdfContext {
 
     /// Render a batch of templates and write the resulting PDFs into the
     /// provided ZIP writer. The writer is finished before returning.
-    #[instrument(skip(context, requests, writer))]
     pub async fn render_batch_to_writer<W>(
         context: Arc<Self>,
         requests: Vec<BatchRenderRequest>,
-        mut writer: ZipResponseWriter<W>,
+        writer: ZipResponseWriter<W>,
     ) -> AppResult<W>
     where
         W: tokio::io::AsyncWrite + Unpin + Send + 'static,
     {
-        context.validate_batch(&requests)?;
+        Self::render_batch_to_writer_with_options(
+            context,
+            requests,
+            &BatchOptions::default(),
+            writer,
+        )
+        .await
+    }
 
-        let mut join_set = JoinSet::new();
-        for request in requests {
-            let BatchRenderRequest {
-                template,
-                file_name,
-                input,
-            } = request;
-            let render_context = Arc::clone(&context);
-            join_set.spawn_blocking(move || {
-                PdfContext::render(render_context, template, input)
-                    .map(|pdf_bytes| (file_name, pdf_bytes))
-            });
-        }
+    /// Like [`PdfContext::render_batch_to_writer`], but failed items are
+    /// handled as configured in `options`.
+    #[instrument(skip(context, requests, writer))]
+    pub async fn render_batch_to_writer_with_options<W>(
+        context: Arc<Self>,
+        requests: Vec<BatchRenderRequest>,
+        options: &BatchOptions,
+        mut writer: ZipResponseWriter<W>,
+    ) -> AppResult<W>
+    where
+        W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let reports = Self::run_batch(context, requests, options, &mut writer).await?;
 
-        while let Some(result) = join_set.join_next().await {
-            let (file_name, pdf_bytes) = result??;
-            writer.add_file(&file_name, &pdf_bytes).await?;
+        if options.errors == BatchErrorMode::Report {
+            let manifest = serde_json::to_vec_pretty(&BatchManifest { items: reports })?;
+            writer.add_file(BATCH_MANIFEST_NAME, &manifest).await?;
         }
 
         writer.finish().await
     }
 
+    /// Render every request with bounded concurrency and hand each finished
+    /// PDF to `sink`, in the order configured in `options`. Returns one report
+    /// per request, in request order.
+    async fn run_batch(
+        context: Arc<Self>,
+        requests: Vec<BatchRenderRequest>,
+        options: &BatchOptions,
+        sink: &mut impl BatchSink,
+    ) -> AppResult<Vec<BatchItemReport>> {
+        context.validate_batch_with_options(&requests, options)?;
+        record_batch(requests.len());
+
+        // Cancel every render still running once this future completes or is
+        // dropped, e.g. because the client went away and writing failed.
+        let batch_cancellation = CancellationToken::new();
+        let _cancel_on_drop = batch_cancellation.clone().drop_guard();
+
+        let concurrency = options.concurrency.map_or_else(
+            || std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            NonZeroUsize::get,
+        );
+        let limiter = options.limiter.as_ref();
+        let mut pending = requests.into_iter();
+        let mut join_set = JoinSet::new();
+        let mut task_items = HashMap::new();
+        let mut reports = Vec::with_capacity(pending.len());
+        let mut item_warnings = Vec::with_capacity(pending.len());
+        // Results waiting for their predecessors in `BatchOrder::Request`.
+        let mut buffered = BTreeMap::new();
+        let mut next_to_write = 0;
+        loop {
+            // Only start new renders while fewer than `concurrency` are in
+            // flight; finished PDFs are written out before the next ones start.
+            // In request order the window also counts buffered results, so
+            // one slow item cannot make the buffer grow without bound.
+            while join_set.len() < concurrency
+                && (options.order == BatchOrder::Completion
+                    || reports.len() < next_to_write + concurrency)
+                && let Some(request) = pending.next()
+            {
+                let BatchRenderRequest {
+                    template,
+                    file_name,
+                    input,
+                    mut options,
+                    pdf,
+                } = request;
+                options
+                    .cancellation
+                    .get_or_insert_with(|| batch_cancellation.child_token());
+                item_warnings.push(
+                    options
+                        .warnings
+                        .get_or_insert_with(RenderWarnings::default)
+                        .clone(),
+                );
+                reports.push(BatchItemReport {
+                    file_name,
+                    template: template.clone(),
+                    status: BatchItemStatus::Ok,
+                    error: None,
+                    diagnostics: Vec::new(),
+                    unknown_fonts: Vec::new(),
+                });
+
+                options.timeout_start.get_or_insert_with(Instant::now);
+                let permit = match limiter {
+                    Some(limiter) => Some(limiter.acquire().await),
+                    None => None,
+                };
+                let render_context = Arc::clone(&context);
+                let task = join_set.spawn_blocking(telemetry::blocking(move || {
+                    let _permit = permit;
+                    PdfContext::render_pdf(render_context, template, input, &pdf, &options)
+                }));
+                task_items.insert(task.id(), reports.len() - 1);
+            }
+
+            let Some(joined) = join_set.join_next_with_id().await else {
+                break;
+            };
+            let (index, result) = match joined {
+                Ok((id, result)) => (task_items[&id], result),
+                Err(error) => (task_items[&error.id()], Err(AppError::TaskJoin(error))),
+            };
+
+            let ready = match options.order {
+                BatchOrder::Completion => vec![(index, result)],
+                BatchOrder::Request => {
+                    buffered.insert(index, result);
+                    let mut ready = Vec::new();
+                    while let Some(result) = buffered.remove(&next_to_write) {
+                        ready.push((next_to_write, result));
+                        next_to_write += 1;
+                    }
+                    ready
+                }
+            };
+
+            for (index, result) in ready {
+                let report = &mut reports[index];
+                report.unknown_fonts = item_warnings[index].unknown_font_families();
+                let counter = match result {
+                    Ok(pdf_bytes) => {
+                        sink.accept(&report.file_name, pdf_bytes).await?;
+                        options
+                            .progress
+                            .as_ref()
+                            .map(|progress| &progress.counters.completed)
+                    }
+                    Err(error) if options.errors == BatchErrorMode::Abort => return Err(error),
+                    Err(error) => {
+                        warn!(file_name = %report.file_name, ?error, "Batch item failed to render");
+                        report.fail(error, options.diagnostics);
+                        options
+                            .progress
+                            .as_ref()
+                            .map(|progress| &progress.counters.failed)
+                    }
+                };
+                if let Some(counter) = counter {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        Ok(reports)
+    }
+
     /// Render a batch of templates and return the resulting ZIP archive as a
     /// byte stream. Bytes are emitted as soon as each PDF is written into the
     /// archive, so callers can pipe the stream straight to a client without
@@ -306,25 +1364,116 @@ impl PdfContext {
         context: Arc<Self>,
         requests: Vec<BatchRenderRequest>,
     ) -> AppResult<ReaderStream<DuplexStream>> {
-        context.validate_batch(&requests)?;
+        Self::render_batch_with_options(context, requests, BatchOptions::default())
+    }
+
+    /// Like [`PdfContext::render_batch`], but failed items are handled as
+    /// configured in `options`; with [`BatchErrorMode::Report`] they are
+    /// listed in a `manifest.json` entry instead of truncating the stream.
+    pub fn render_batch_with_options(
+        context: Arc<Self>,
+        requests: Vec<BatchRenderRequest>,
+        options: BatchOptions,
+    ) -> AppResult<ReaderStream<DuplexStream>> {
+        context.validate_batch_with_options(&requests, &options)?;
 
         let (reader, tx) = tokio::io::duplex(64 * 1024);
         let writer = ZipResponseWriter::new(tx);
 
         tokio::spawn(async move {
-            if let Err(error) = Self::render_batch_to_writer(context, requests, writer).await {
+            if let Err(error) =
+                Self::render_batch_to_writer_with_options(context, requests, &options, writer).await
+            {
                 tracing::error!(?error, "Failed to stream ZIP batch response");
             }
         });
 
         Ok(ReaderStream::new(reader))
     }
+
+    /// Render a batch into one combined PDF, with the items in request order
+    /// and a bookmark named after each item's `file_name`.
+    ///
+    /// The whole result is built in memory. A single PDF has no room for a
+    /// manifest, so [`BatchErrorMode::Report`] is rejected and any failing
+    /// item fails the batch. The combined document keeps the pages of every
+    /// item but not their tags or metadata, so it does not claim conformance
+    /// with the PDF standards the items were exported with.
+    pub async fn render_batch_merged(
+        context: Arc<Self>,
+        requests: Vec<BatchRenderRequest>,
+        options: &BatchOptions,
+    ) -> AppResult<Vec<u8>> {
+        if options.errors == BatchErrorMode::Report {
+            return Err(AppError::InvalidRenderOptions(
+                "a merged PDF cannot report per-item errors".into(),
+            ));
+        }
+        if requests.is_empty() {
+            return Err(AppError::InvalidRenderOptions(
+                "cannot merge an empty batch".into(),
+            ));
+        }
+
+        let options = BatchOptions {
+            order: BatchOrder::Request,
+            ..options.clone()
+        };
+        let mut documents = Vec::with_capacity(requests.len());
+        Self::run_batch(context, requests, &options, &mut documents).await?;
+
+        let merge_start = Instant::now();
+        let merged =
+            tokio::task::spawn_blocking(telemetry::blocking(move || merge_pdfs(&documents)))
+                .await??;
+        debug!("Merging PDFs took {} ms", merge_start.elapsed().as_millis());
+        Ok(merged)
+    }
+}
+
+/// Destination for the PDFs of a batch as they finish.
+trait BatchSink {
+    /// Take the rendered PDF of the item named `file_name`.
+    fn accept(
+        &mut self,
+        file_name: &str,
+        pdf_bytes: Vec<u8>,
+    ) -> impl Future<Output = AppResult<()>> + Send;
+}
+
+impl<W> BatchSink for ZipResponseWriter<W>
+where
+    W: tokio::io::AsyncWrite + Unpin + Send,
+{
+    async fn accept(&mut self, file_name: &str, pdf_bytes: Vec<u8>) -> AppResult<()> {
+        self.add_file(file_name, &pdf_bytes).await
+    }
+}
+
+impl BatchSink for Vec<(String, Vec<u8>)> {
+    async fn accept(&mut self, file_name: &str, pdf_bytes: Vec<u8>) -> AppResult<()> {
+        self.push((file_name.to_owned(), pdf_bytes));
+        Ok(())
+    }
+}
+
+impl RenderInput {
+    /// Build the error for a missing file, reporting a missing package when
+    /// the file belongs to one that is not in the package directory.
+    fn not_found(&self, id: FileId) -> FileError {
+        match id.root() {
+            VirtualRoot::Package(spec) if !self.context.packages.contains(spec) => {
+                FileError::Package(PackageError::NotFound(spec.clone()))
+            }
+            _ => FileError::NotFound(id.vpath().get_without_slash().into()),
+        }
+    }
 }
 
 impl World for RenderInput {
-    /// Provide access to the preloaded Typst standard library.
+    /// Provide the standard library, carrying this render's `sys.inputs` if any.
     fn library(&self) -> &LazyHash<Library> {
-        &self.context.library
+        self.library.as_ref().unwrap_or(&self.context.library)
     }
 
     /// Expose the available fonts to the compiler.
@@ -339,6 +1488,7 @@ impl World for RenderInput {
 
     /// Retrieve a Typst source by its ID or report a missing file error.
     fn source(&self, id: FileId) -> FileResult<Source> {
+        self.interrupt.check_file()?;
         for source in &self.context.sources {
             if source.id() == id {
                 trace!(?id, "Resolved source file");
@@ -347,11 +1497,13 @@ impl World for RenderInput {
         }
 
         trace!(?id, "Source file not found");
-        Err(FileError::NotFound(id.vpath().get_without_slash().into()))
+        Err(self.not_found(id))
     }
 
     /// Retrieve a binary asset by its ID, including the injected JSON input.
     fn file(&self, id: FileId) -> FileResult<Bytes> {
+        self.interrupt.check_file()?;
+
         // if the file we need is the input file, pass that
         if self.input_data.0 == id {
             trace!(?id, "Served synthetic JSON input");
@@ -359,29 +1511,35 @@ impl World for RenderInput {
         }
 
         // otherwise it must be one of the other files
-        self.context
-            .assets
-            .get(&id)
-            .cloned()
-            .inspect(|_| {
-                trace!(?id, "Served binary asset");
-            })
-            .ok_or_else(|| {
+        if let Some(bytes) = self.context.assets.get(&id) {
+            trace!(?id, "Served binary asset");
+            return Ok(bytes.clone());
+        }
+        match &self.context.lazy_assets {
+            Some(lazy) if matches!(id.root(), VirtualRoot::Project) => lazy.get(id),
+            _ => {
                 trace!(?id, "Binary asset not found");
-                FileError::NotFound(id.vpath().get_without_slash().into())
-            })
+                Err(self.not_found(id))
+            }
+        }
     }
 
     /// Return a font from the context by index, if present.
     fn font(&self, index: usize) -> Option<Font> {
-        self.context.fonts.get(index).cloned()
+        self.context.fonts.get(index)?.get()
     }
 
-    /// Provide the current date, optionally offset by a duration, to the document.
-    fn today(&self, offset: Option<Duration>) -> Option<Datetime> {
+    /// Provide the current (or pinned) date, optionally offset by a duration,
+    /// to the document.
+    fn today(&self, offset: Option<TypstDuration>) -> Option<Datetime> {
+        if self.interrupt.check().is_err() {
+            return None;
+        }
+
+        let now = self.now.unwrap_or_else(Utc::now);
         let datetime = match offset {
-            Some(offset) => chrono::Utc::now() + chrono::Duration::seconds(offset.seconds() as i64),
-            None => chrono::Utc::now(),
+            Some(offset) => now + chrono::Duration::seconds(offset.seconds() as i64),
+            None => now,
         };
         trace!(?offset, ?datetime, "Providing current datetime");
 
@@ -428,6 +1586,507 @@ mod test {
         );
     }
 
+    /// Verify that PNG export yields one valid image per page and honours page selection.
+    #[test]
+    fn test_png_generation() {
+        crate::logging::init_for_tests();
+        let context = Arc::new(PdfContext::from_directory("./assets").unwrap());
+        let input = serde_json::json!({ "name": "Png", "list": ["Item"] });
+
+        let pages = PdfContext::render_png(
+            Arc::clone(&context),
+            "example.typ".to_string(),
+            input.clone(),
+            &PngOptions::default(),
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert!(!pages.is_empty(), "expected at least one rendered page");
+        for page in &pages {
+            assert!(page.starts_with(b"\x89PNG"), "expected PNG signature");
+        }
+
+        let first = PdfContext::render_png(
+            Arc::clone(&context),
+            "example.typ".to_string(),
+            input.clone(),
+            &PngOptions {
+                pages: PageSelection::Single(1),
+                ..Default::default()
+            },
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(first.len(), 1);
+
+        let out_of_range = PdfContext::render_png(
+            Arc::clone(&context),
+            "example.typ".to_string(),
+            input.clone(),
+            &PngOptions {
+                pages: PageSelection::Single(pages.len() + 1),
+                ..Default::default()
+            },
+            &RenderOptions::default(),
+        );
+        assert!(matches!(out_of_range, Err(AppError::PageOutOfRange { .. })));
+
+        let too_large = PdfContext::render_png(
+            context,
+            "example.typ".to_string(),
+            input,
+            &PngOptions {
+                pixel_per_pt: PngOptions::MAX_PIXEL_PER_PT * 2.0,
+                ..Default::default()
+            },
+            &RenderOptions::default(),
+        );
+        assert!(matches!(too_large, Err(AppError::InvalidRenderOptions(_))));
+    }
+
+    /// Verify that SVG export honours merging and single page selection.
+    #[test]
+    fn test_svg_generation() {
+        crate::logging::init_for_tests();
+        let context = Arc::new(PdfContext::from_directory("./assets").unwrap());
+        let input = serde_json::json!({ "name": "Svg", "list": ["Item"] });
+
+        let merged = PdfContext::render_svg(
+            Arc::clone(&context),
+            "example.typ".to_string(),
+            input.clone(),
+            &SvgOptions::default(),
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(merged.len(), 1);
+        assert!(merged[0].contains("<svg"), "expected SVG markup");
+
+        let single = PdfContext::render_svg(
+            context,
+            "example.typ".to_string(),
+            input,
+            &SvgOptions {
+                pages: PageSelection::Single(1),
+                merge_pages: false,
+            },
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(single.len(), 1);
+        assert!(single[0].contains("<svg"), "expected SVG markup");
+    }
+
+    /// A render past its deadline fails with a dedicated timeout error.
+    #[test]
+    fn render_honours_timeout() {
+        crate::logging::init_for_tests();
+        let context = Arc::new(PdfContext::from_directory("./assets").unwrap());
+
+        let result = PdfContext::render_with_options(
+            context,
+            "example.typ".to_string(),
+            serde_json::json!({ "name": "Slow", "list": ["Item"] }),
+            &RenderOptions {
+                timeout: Some(Duration::ZERO),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(AppError::RenderTimeout(_))));
+    }
+
+    /// Templates can import packages from a local package directory, while
+    /// packages missing from it produce a compile error.
+    #[test]
+    fn render_resolves_local_packages() {
+        crate::logging::init_for_tests();
+        let root = std::env::temp_dir().join(format!("tws-packages-{}", Uuid::new_v4()));
+        let assets = root.join("assets");
+        let package = root.join("packages/local/greeter/0.1.0");
+        std::fs::create_dir_all(&assets).unwrap();
+        std::fs::create_dir_all(package.join("src")).unwrap();
+        std::fs::write(
+            package.join("typst.toml"),
+            "[package]\nname = \"greeter\"\nversion = \"0.1.0\"\nentrypoint = \"lib.typ\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            package.join("lib.typ"),
+            "#import \"src/util.typ\": shout\n#let greet(name) = [Hello #shout(name)]",
+        )
+        .unwrap();
+        std::fs::write(
+            package.join("src/util.typ"),
+            "#let shout(name) = upper(name)",
+        )
+        .unwrap();
+        std::fs::write(
+            assets.join("greeting.typ"),
+            "#import \"@local/greeter:0.1.0\": greet\n#greet(\"World\")",
+        )
+        .unwrap();
+        std::fs::write(
+            assets.join("missing.typ"),
+            "#import \"@local/absent:1.0.0\": nothing",
+        )
+        .unwrap();
+
+        let context = Arc::new(
+            PdfContext::from_directory_with_options(
+                &assets,
+                &ContextOptions {
+                    packages: Some(root.join("packages")),
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            context.template_names(),
+            vec!["greeting.typ", "missing.typ"]
+        );
+        assert!(!context.has_template("lib.typ"));
+
+        let pdf = PdfContext::render(
+            Arc::clone(&context),
+            "greeting.typ".to_string(),
+            serde_json::json!({}),
+        )
+        .unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+
+        let result = PdfContext::render(context, "missing.typ".to_string(), serde_json::json!({}));
+        let Err(AppError::TypstCompilation(diagnostics)) = result else {
+            panic!("expected a compilation error");
+        };
+        assert!(
+            diagnostics[0].message.contains("@local/absent:1.0.0"),
+            "unexpected diagnostic: {}",
+            diagnostics[0].message
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Lazy contexts read images from disk on demand under the same paths as
+    /// eager ones, while sidecars are still loaded up front.
+    #[test]
+    fn lazy_assets_resolve_like_eager_ones() {
+        crate::logging::init_for_tests();
+        let root = std::env::temp_dir().join(format!("tws-lazy-assets-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("images/icons")).unwrap();
+        std::fs::write(
+            root.join("images/icons/dot.svg"),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><circle cx="5" cy="5" r="5"/></svg>"#,
+        )
+        .unwrap();
+        std::fs::write(root.join("doc.typ"), "#image(\"dot.svg\", alt: \"Dot\")").unwrap();
+        std::fs::write(
+            root.join("nested.typ"),
+            "#image(\"images/icons/dot.svg\", alt: \"Dot\")",
+        )
+        .unwrap();
+        std::fs::write(root.join("doc.meta.json"), r#"{"description": "Dots"}"#).unwrap();
+
+        for lazy_assets in [None, Some(1024)] {
+            let context = Arc::new(
+                PdfContext::from_directory_with_options(
+                    &root,
+                    &ContextOptions {
+                        lazy_assets,
+                        ..Default::default()
+                    },
+                )
+                .unwrap(),
+            );
+            let icon = file_id_from_path(Path::new("dot.svg")).unwrap();
+            assert_eq!(context.assets.contains_key(&icon), lazy_assets.is_none());
+            assert_eq!(context.catalog()[0].description.as_deref(), Some("Dots"));
+
+            let pdf = PdfContext::render(
+                Arc::clone(&context),
+                "doc.typ".to_string(),
+                serde_json::json!({}),
+            )
+            .unwrap();
+            assert!(pdf.starts_with(b"%PDF"));
+
+            let result =
+                PdfContext::render(context, "nested.typ".to_string(), serde_json::json!({}));
+            assert!(
+                matches!(result, Err(AppError::TypstCompilation(_))),
+                "files are keyed relative to their own directory"
+            );
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// Per-request inputs reach the template as `sys.inputs`.
+    #[test]
+    fn render_exposes_sys_inputs() {
+        crate::logging::init_for_tests();
+        let template: &[u8] = b"#assert.eq(sys.inputs.at(\"name\", default: none), \"World\")";
+        let context = Arc::new(PdfContext::from_assets(&[("inputs.typ", template)]).unwrap());
+
+        let request: BatchRenderRequest = serde_json::from_value(serde_json::json!({
+            "template": "inputs.typ",
+            "file_name": "inputs.pdf",
+            "input": {},
+            "inputs": { "name": "World" }
+        }))
+        .unwrap();
+        let pdf = PdfContext::render_with_options(
+            Arc::clone(&context),
+            request.template,
+            request.input,
+            &request.options,
+        )
+        .unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+
+        let result = PdfContext::render(context, "inputs.typ".to_string(), serde_json::json!({}));
+        assert!(matches!(result, Err(AppError::TypstCompilation(_))));
+    }
+
+    /// Unknown font families are collected per render, and fonts are listed
+    /// with the file they come from.
+    #[test]
+    fn render_reports_unknown_font_families() {
+        crate::logging::init_for_tests();
+        let font = std::fs::read("./assets/Bagnard.otf").unwrap();
+        let template: &[u8] = b"#set text(font: (\"Missing Sans\", \"Bagnard\"))\nHello";
+        let context = Arc::new(
+            PdfContext::from_assets(&[("doc.typ", template), ("fonts/Bagnard.otf", &font)])
+                .unwrap(),
+        );
+
+        let fonts = context.fonts();
+        let bagnard = fonts
+            .iter()
+            .find(|font| font.family == "Bagnard")
+            .expect("Bagnard should be listed");
+        assert_eq!(bagnard.source, FontSource::Project);
+        assert_eq!(bagnard.file.as_deref(), Some("fonts/Bagnard.otf"));
+        assert_eq!(bagnard.weight, 400);
+        assert!(bagnard.coverage.codepoints > 0);
+        assert!(
+            bagnard
+                .coverage
+                .scripts
+                .iter()
+                .any(|script| script == "Latin")
+        );
+
+        let warnings = RenderWarnings::default();
+        PdfContext::render_pdf(
+            context,
+            "doc.typ".into(),
+            serde_json::json!({}),
+            &PdfOptions::default(),
+            &RenderOptions {
+                warnings: Some(warnings.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(warnings.unknown_font_families(), vec!["missing sans"]);
+        assert!(!warnings.diagnostics().is_empty());
+    }
+
+    /// Unknown font families are recognised by the wording of Typst's
+    /// warning, which is not a stable interface; this fails if a Typst
+    /// upgrade changes it.
+    #[test]
+    fn unknown_font_warning_matches_typst() {
+        crate::logging::init_for_tests();
+        let context = Arc::new(
+            PdfContext::from_assets(&[("doc.typ", b"#set text(font: \"Missing Sans\")\nHello")])
+                .unwrap(),
+        );
+        let warnings = RenderWarnings::default();
+        PdfContext::render_pdf(
+            context,
+            "doc.typ".into(),
+            serde_json::json!({}),
+            &PdfOptions::default(),
+            &RenderOptions {
+                warnings: Some(warnings.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let messages = warnings
+            .diagnostics()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message.to_string())
+            .collect::<Vec<_>>();
+        assert!(
+            messages.contains(&format!("{UNKNOWN_FONT_WARNING}missing sans")),
+            "unexpected warnings: {messages:?}"
+        );
+    }
+
+    /// The catalog combines each template's source with its sidecar files.
+    #[test]
+    fn catalog_reads_template_sidecars() {
+        crate::logging::init_for_tests();
+        let meta: &[u8] = br#"{"description": "An invoice", "sample_input": {"total": 1}}"#;
+        let schema: &[u8] = br#"{"type": "object", "required": ["total"]}"#;
+        let context = PdfContext::from_assets(&[
+            ("invoices/invoice.typ", b"Total"),
+            ("invoices/invoice.meta.json", meta),
+            ("invoices/invoice.schema.json", schema),
+            ("plain.typ", b""),
+        ])
+        .unwrap();
+
+        let catalog = context.catalog();
+        assert_eq!(catalog.len(), 2);
+        let invoice = &catalog[0];
+        assert_eq!(invoice.name, "invoice.typ");
+        assert_eq!(invoice.path, "/invoices/invoice.typ");
+        assert_eq!(invoice.size, 5);
+        assert!(invoice.hash.starts_with("sha256:"));
+        assert_eq!(invoice.description.as_deref(), Some("An invoice"));
+        assert_eq!(
+            invoice.input_schema.as_ref().unwrap()["required"][0],
+            "total"
+        );
+        assert_eq!(invoice.sample_input, Some(serde_json::json!({"total": 1})));
+        assert!(invoice.modified.is_none());
+
+        let plain = &catalog[1];
+        assert_eq!(plain.path, "/plain.typ");
+        assert!(plain.description.is_none() && plain.input_schema.is_none());
+
+        let result = PdfContext::from_assets(&[("doc.typ", b""), ("doc.meta.json", b"{")]);
+        assert!(matches!(result, Err(AppError::TemplateMetadata { .. })));
+    }
+
+    /// Template sidecars provide PDF defaults that requests override per field.
+    #[test]
+    fn render_pdf_merges_template_defaults() {
+        crate::logging::init_for_tests();
+        let template: &[u8] = b"First #pagebreak() Second";
+        let sidecar: &[u8] = br#"{"pdf": {"standards": ["2.0"], "identifier": "doc"}}"#;
+        let context = Arc::new(
+            PdfContext::from_assets(&[("doc.typ", template), ("doc.options.json", sidecar)])
+                .unwrap(),
+        );
+
+        let pdf = PdfContext::render(
+            Arc::clone(&context),
+            "doc.typ".into(),
+            serde_json::json!({}),
+        )
+        .unwrap();
+        assert!(
+            pdf.starts_with(b"%PDF-2.0"),
+            "expected the template default"
+        );
+
+        let pdf = PdfContext::render_pdf(
+            Arc::clone(&context),
+            "doc.typ".into(),
+            serde_json::json!({}),
+            &PdfOptions {
+                standards: Some(vec![PdfStandard::V_1_7]),
+                timestamp: Some(PdfTimestamp::Omit),
+                page_ranges: Some("2-".into()),
+                ..Default::default()
+            },
+            &RenderOptions::default(),
+        )
+        .unwrap();
+        assert!(
+            pdf.starts_with(b"%PDF-1.7"),
+            "expected the request override"
+        );
+
+        let result = PdfContext::render_pdf(
+            context,
+            "doc.typ".into(),
+            serde_json::json!({}),
+            &PdfOptions {
+                standards: Some(vec![PdfStandard::A_2a, PdfStandard::A_3b]),
+                ..Default::default()
+            },
+            &RenderOptions::default(),
+        );
+        assert!(matches!(result, Err(AppError::InvalidRenderOptions(_))));
+    }
+
+    /// Page ranges follow the Typst CLI's `--pages` syntax.
+    #[test]
+    fn parse_page_ranges_accepts_cli_syntax() {
+        let ranges = parse_page_ranges("1-2, 4,6-").unwrap();
+        let included = (1..=7)
+            .filter(|page| ranges.includes_page(NonZeroUsize::new(*page).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(included, vec![1, 2, 4, 6, 7]);
+
+        for invalid in ["", "0", "a-2", "1,,2"] {
+            assert!(parse_page_ranges(invalid).is_err(), "accepted `{invalid}`");
+        }
+    }
+
+    /// A malformed sidecar fails loading instead of being ignored.
+    #[test]
+    fn invalid_template_options_fail_loading() {
+        let result = PdfContext::from_assets(&[
+            ("doc.typ", b"Doc".as_slice()),
+            (
+                "doc.options.json",
+                b"{\"pdf\": {\"standards\": 7}}".as_slice(),
+            ),
+        ]);
+        assert!(matches!(result, Err(AppError::TemplateOptions { .. })));
+    }
+
+    /// Pinning the clock makes repeated renders byte-identical, and the
+    /// template sees the pinned date.
+    #[test]
+    fn pinned_now_renders_reproducibly() {
+        crate::logging::init_for_tests();
+        let template: &[u8] = b"#assert.eq(datetime.today().year(), 2001)\n#lorem(20)";
+        let context = Arc::new(PdfContext::from_assets(&[("dated.typ", template)]).unwrap());
+        let options = RenderOptions {
+            now: Some("2001-02-03T04:05:06Z".parse().unwrap()),
+            ..Default::default()
+        };
+
+        let render = || {
+            PdfContext::render_with_options(
+                Arc::clone(&context),
+                "dated.typ".into(),
+                serde_json::json!({ "id": 1 }),
+                &options,
+            )
+            .unwrap()
+        };
+        assert_eq!(render(), render());
+    }
+
+    /// A cancelled render fails instead of producing output.
+    #[test]
+    fn render_honours_cancellation() {
+        crate::logging::init_for_tests();
+        let context = Arc::new(PdfContext::from_directory("./assets").unwrap());
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+
+        let result = PdfContext::render_with_options(
+            context,
+            "example.typ".to_string(),
+            serde_json::json!({ "name": "Gone", "list": ["Item"] }),
+            &RenderOptions {
+                cancellation: Some(cancellation),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(result, Err(AppError::RenderCancelled)));
+    }
+
     /// Verify that `render_batch` produces a valid zip archive with each rendered PDF.
     #[tokio::test]
     async fn test_render_batch_produces_zip() {
@@ -439,11 +2098,13 @@ mod test {
                 template: "example.typ".to_string(),
                 file_name: "first.pdf".to_string(),
                 input: serde_json::json!({ "name": "One", "list": ["Item"] }),
+                ..Default::default()
             },
             BatchRenderRequest {
                 template: "example.typ".to_string(),
                 file_name: "second.pdf".to_string(),
                 input: serde_json::json!({ "name": "Two", "list": ["Item"] }),
+                ..Default::default()
             },
         ];
 
@@ -465,6 +2126,232 @@ mod test {
         }
     }
 
+    /// In report mode failed items are listed in the manifest while the
+    /// remaining PDFs still land in the archive.
+    #[tokio::test]
+    async fn render_batch_reports_failed_items() {
+        crate::logging::init_for_tests();
+        let context = Arc::new(
+            PdfContext::from_assets(&[
+                ("good.typ", b"Good".as_slice()),
+                ("bad.typ", b"#undefined-function()".as_slice()),
+            ])
+            .unwrap(),
+        );
+        let requests = vec![
+            BatchRenderRequest {
+                template: "bad.typ".to_string(),
+                file_name: "bad.pdf".to_string(),
+                ..Default::default()
+            },
+            BatchRenderRequest {
+                template: "good.typ".to_string(),
+                file_name: "good.pdf".to_string(),
+                ..Default::default()
+            },
+        ];
+        let options = BatchOptions {
+            errors: BatchErrorMode::Report,
+            diagnostics: true,
+            ..Default::default()
+        };
+
+        let stream = PdfContext::render_batch_with_options(context, requests, options).unwrap();
+        let mut reader = tokio_util::io::StreamReader::new(stream);
+        let mut zip_bytes = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut zip_bytes)
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(zip_bytes)).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert!(archive.by_name("good.pdf").is_ok());
+        assert!(archive.by_name("bad.pdf").is_err());
+
+        let manifest: serde_json::Value =
+            serde_json::from_reader(archive.by_name(BATCH_MANIFEST_NAME).unwrap()).unwrap();
+        let items = manifest["items"].as_array().unwrap();
+        assert_eq!(items[0]["file_name"], "bad.pdf");
+        assert_eq!(items[0]["status"], "error");
+        assert_eq!(items[0]["error"], "Typst compilation failed");
+        assert!(!items[0]["diagnostics"].as_array().unwrap().is_empty());
+        assert_eq!(items[1]["file_name"], "good.pdf");
+        assert_eq!(items[1]["status"], "ok");
+    }
+
+    /// A bounded batch still renders every item and returns its slots to the
+    /// shared limiter.
+    #[tokio::test]
+    async fn render_batch_respects_concurrency_limits() {
+        crate::logging::init_for_tests();
+        let context =
+            Arc::new(PdfContext::from_assets(&[("page.typ", b"Page".as_slice())]).unwrap());
+        let limiter = RenderLimiter::new(NonZeroUsize::new(1).unwrap());
+        let requests = (0..5)
+            .map(|index| BatchRenderRequest {
+                template: "page.typ".to_string(),
+                file_name: format!("page-{index}.pdf"),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let (reader, tx) = tokio::io::duplex(64 * 1024);
+        let read = tokio::spawn(async move {
+            let mut reader = reader;
+            let mut zip_bytes = Vec::new();
+            tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut zip_bytes)
+                .await
+                .unwrap();
+            zip_bytes
+        });
+        PdfContext::render_batch_to_writer_with_options(
+            context,
+            requests,
+            &BatchOptions {
+                concurrency: NonZeroUsize::new(2),
+                limiter: Some(limiter.clone()),
+                ..Default::default()
+            },
+            ZipResponseWriter::new(tx),
+        )
+        .await
+        .unwrap();
+
+        let archive = zip::ZipArchive::new(std::io::Cursor::new(read.await.unwrap())).unwrap();
+        assert_eq!(archive.len(), 5);
+        assert_eq!(limiter.available(), limiter.limit());
+    }
+
+    /// A batch item's time limit includes the time it waits for a limiter
+    /// slot.
+    #[tokio::test]
+    async fn render_batch_timeout_covers_limiter_wait() {
+        crate::logging::init_for_tests();
+        let context =
+            Arc::new(PdfContext::from_assets(&[("page.typ", b"Page".as_slice())]).unwrap());
+        let limiter = RenderLimiter::new(NonZeroUsize::MIN);
+        let busy = limiter.acquire().await;
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            drop(busy);
+        });
+
+        let mut documents = Vec::new();
+        let options = BatchOptions {
+            errors: BatchErrorMode::Report,
+            limiter: Some(limiter),
+            ..Default::default()
+        };
+        let requests = vec![BatchRenderRequest {
+            template: "page.typ".to_string(),
+            file_name: "page.pdf".to_string(),
+            options: RenderOptions {
+                timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }];
+        let reports = PdfContext::run_batch(context, requests, &options, &mut documents)
+            .await
+            .unwrap();
+        release.await.unwrap();
+
+        assert!(documents.is_empty());
+        assert_eq!(reports[0].status, BatchItemStatus::Error);
+        assert_eq!(
+            reports[0].error.as_deref(),
+            Some("Render exceeded its time limit")
+        );
+    }
+
+    /// In request order the archive entries follow the request list even when
+    /// a slow first item finishes last.
+    #[tokio::test]
+    async fn render_batch_writes_request_order() {
+        crate::logging::init_for_tests();
+        let context = Arc::new(
+            PdfContext::from_assets(&[
+                (
+                    "slow.typ",
+                    b"#for _ in range(40) { lorem(400); pagebreak() }".as_slice(),
+                ),
+                ("fast.typ", b"Fast".as_slice()),
+            ])
+            .unwrap(),
+        );
+        let requests = ["slow.typ", "fast.typ", "fast.typ", "fast.typ", "fast.typ"]
+            .iter()
+            .enumerate()
+            .map(|(index, template)| BatchRenderRequest {
+                template: template.to_string(),
+                file_name: format!("{index}.pdf"),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let options = BatchOptions {
+            order: BatchOrder::Request,
+            concurrency: NonZeroUsize::new(3),
+            ..Default::default()
+        };
+
+        let stream = PdfContext::render_batch_with_options(context, requests, options).unwrap();
+        let mut reader = tokio_util::io::StreamReader::new(stream);
+        let mut zip_bytes = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut zip_bytes)
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(zip_bytes)).unwrap();
+        let names = (0..archive.len())
+            .map(|index| archive.by_index(index).unwrap().name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["0.pdf", "1.pdf", "2.pdf", "3.pdf", "4.pdf"]);
+    }
+
+    /// Merged batches keep request order and bookmark each item's first page.
+    #[tokio::test]
+    async fn render_batch_merged_bookmarks_each_item() {
+        crate::logging::init_for_tests();
+        let context = Arc::new(
+            PdfContext::from_assets(&[
+                ("two.typ", b"One #pagebreak() Two".as_slice()),
+                ("one.typ", b"Single".as_slice()),
+            ])
+            .unwrap(),
+        );
+        let requests = [("two.typ", "first.pdf"), ("one.typ", "second.pdf")]
+            .iter()
+            .map(|(template, file_name)| BatchRenderRequest {
+                template: template.to_string(),
+                file_name: file_name.to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let bytes =
+            PdfContext::render_batch_merged(context.clone(), requests.clone(), &Default::default())
+                .await
+                .unwrap();
+        let merged = lopdf::Document::load_mem(&bytes).unwrap();
+        assert_eq!(merged.get_pages().len(), 3);
+        let toc = merged.get_toc().unwrap();
+        let bookmarks = toc
+            .toc
+            .iter()
+            .map(|entry| (entry.title.as_str(), entry.page))
+            .collect::<Vec<_>>();
+        assert_eq!(bookmarks, [("first.pdf", 1), ("second.pdf", 3)]);
+
+        let report = BatchOptions {
+            errors: BatchErrorMode::Report,
+            ..Default::default()
+        };
+        assert!(matches!(
+            PdfContext::render_batch_merged(context, requests, &report).await,
+            Err(AppError::InvalidRenderOptions(_))
+        ));
+    }
+
     /// Validation must surface a synchronous error before any stream is
     /// produced, so callers can return a 4xx instead of streaming a body
     /// that fails mid-flight.
@@ -477,6 +2364,7 @@ mod test {
             template: "does-not-exist.typ".to_string(),
             file_name: "missing.pdf".to_string(),
             input: serde_json::json!({}),
+            ..Default::default()
         }];
 
         let result = PdfContext::render_batch(context, requests);
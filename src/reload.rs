@@ -0,0 +1,300 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock, TryLockError},
+};
+
+use tracing::{error, info};
+
+use crate::{
+    error::{AppError, AppResult},
+    pdf::{ContextOptions, PdfContext},
+};
+
+/// A cheaply clonable handle to the current [`PdfContext`] that can be
+/// swapped atomically.
+///
+/// Each render grabs the current context via [`ContextHandle::load`] and keeps
+/// its own `Arc`, so a reload only affects requests that start afterwards;
+/// in-flight renders finish on the context they started with.
+#[derive(Clone)]
+pub struct ContextHandle {
+    current: Arc<RwLock<Arc<PdfContext>>>,
+    origin: Option<Arc<Origin>>,
+}
+
+/// Where a [`ContextHandle`] reloads its context from.
+struct Origin {
+    directory: PathBuf,
+    options: ContextOptions,
+    /// Held for a whole reload, so a slow reload cannot replace the result
+    /// of one that started after it.
+    reloading: Mutex<()>,
+}
+
+impl ContextHandle {
+    /// Load a context from a directory and remember the directory for reloads.
+    pub fn from_directory(path: impl AsRef<Path>) -> AppResult<Self> {
+        Self::from_directory_with_options(path, ContextOptions::default())
+    }
+
+    /// Load a context from a directory with extra load options, remembering
+    /// both for reloads.
+    pub fn from_directory_with_options(
+        path: impl AsRef<Path>,
+        options: ContextOptions,
+    ) -> AppResult<Self> {
+        let path = path.as_ref();
+        let context = PdfContext::from_directory_with_options(path, &options)?;
+        Ok(Self {
+            current: Arc::new(RwLock::new(Arc::new(context))),
+            origin: Some(Arc::new(Origin {
+                directory: path.to_path_buf(),
+                options,
+                reloading: Mutex::new(()),
+            })),
+        })
+    }
+
+    /// The directory the context is reloaded from, if any.
+    pub fn directory(&self) -> Option<&Path> {
+        self.origin
+            .as_deref()
+            .map(|origin| origin.directory.as_path())
+    }
+
+    /// Return the context new requests should render with.
+    pub fn load(&self) -> Arc<PdfContext> {
+        let current = self
+            .current
+            .read()
+            .unwrap_or_else(|poison| poison.into_inner());
+        Arc::clone(&current)
+    }
+
+    /// Swap in a new context for subsequent requests.
+    pub fn replace(&self, context: PdfContext) {
+        let mut current = self
+            .current
+            .write()
+            .unwrap_or_else(|poison| poison.into_inner());
+        *current = Arc::new(context);
+    }
+
+    /// Rebuild the context from its directory and swap it in.
+    ///
+    /// On failure the error is logged and the previous context stays active.
+    /// Concurrent reloads run one after another, so the context built last
+    /// is the one that stays. This reads and parses every asset, so call it
+    /// from a blocking context.
+    pub fn reload(&self) -> AppResult<()> {
+        let origin = self.origin.as_deref().ok_or(AppError::ReloadUnavailable)?;
+        let _reloading = origin
+            .reloading
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        self.rebuild(origin)
+    }
+
+    /// Like [`ContextHandle::reload`], but fail with
+    /// [`AppError::ReloadInProgress`] instead of waiting when another reload
+    /// is running, so callers cannot pile up blocked threads.
+    pub fn try_reload(&self) -> AppResult<()> {
+        let origin = self.origin.as_deref().ok_or(AppError::ReloadUnavailable)?;
+        let _reloading = match origin.reloading.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poison)) => poison.into_inner(),
+            Err(TryLockError::WouldBlock) => return Err(AppError::ReloadInProgress),
+        };
+        self.rebuild(origin)
+    }
+
+    /// Load the context from `origin` and swap it in; the caller holds the
+    /// reload lock.
+    fn rebuild(&self, origin: &Origin) -> AppResult<()> {
+        info!(path = %origin.directory.display(), "Reloading Typst assets");
+
+        match PdfContext::from_directory_with_options(&origin.directory, &origin.options) {
+            Ok(context) => {
+                let templates = context.template_names().len();
+                self.replace(context);
+                info!(templates, "Reloaded Typst assets");
+                Ok(())
+            }
+            Err(error) => {
+                error!(
+                    ?error,
+                    "Failed to reload Typst assets; keeping previous context"
+                );
+                Err(error)
+            }
+        }
+    }
+}
+
+impl From<PdfContext> for ContextHandle {
+    fn from(context: PdfContext) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(context))),
+            origin: None,
+        }
+    }
+}
+
+#[cfg(feature = "hot-reload")]
+pub use watch::ContextWatcher;
+
+#[cfg(feature = "hot-reload")]
+mod watch {
+    use std::time::Duration;
+
+    use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+    use tokio::{sync::mpsc, task::JoinHandle};
+    use tracing::{debug, error, info, trace};
+
+    use super::ContextHandle;
+    use crate::error::{AppError, AppResult};
+
+    /// Keeps a directory watch alive; dropping it stops reloading.
+    pub struct ContextWatcher {
+        _watcher: RecommendedWatcher,
+        task: JoinHandle<()>,
+    }
+
+    impl Drop for ContextWatcher {
+        fn drop(&mut self) {
+            self.task.abort();
+        }
+    }
+
+    impl ContextHandle {
+        /// Watch the context's directory and reload whenever files change.
+        ///
+        /// Bursts of file events are coalesced: a reload starts once no new
+        /// events arrived for `debounce`. Must be called from within a Tokio
+        /// runtime.
+        pub fn watch(&self, debounce: Duration) -> AppResult<ContextWatcher> {
+            let directory = self.directory().ok_or(AppError::ReloadUnavailable)?;
+
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let mut watcher = notify::recommended_watcher(move |event| {
+                // The receiver only disappears when the watcher is dropped.
+                let _ = tx.send(event);
+            })?;
+            watcher.watch(directory, RecursiveMode::Recursive)?;
+            info!(path = %directory.display(), "Watching assets directory for changes");
+
+            let handle = self.clone();
+            let task = tokio::spawn(async move {
+                while let Some(event) = rx.recv().await {
+                    trace!(?event, "Received file system event");
+                    // Swallow follow-up events until the directory settles.
+                    while let Ok(Some(event)) = tokio::time::timeout(debounce, rx.recv()).await {
+                        trace!(?event, "Coalescing file system event");
+                    }
+
+                    debug!("Assets directory changed");
+                    let reload_handle = handle.clone();
+                    let reload =
+                        tokio::task::spawn_blocking(crate::telemetry::blocking(move || {
+                            reload_handle.reload()
+                        }));
+                    if let Err(error) = reload.await {
+                        error!(?error, "Reload task failed to complete");
+                    }
+                }
+            });
+
+            Ok(ContextWatcher {
+                _watcher: watcher,
+                task,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContextHandle;
+    use crate::{error::AppError, pdf::PdfContext};
+
+    /// Reloading swaps the context for new loads while old handles stay valid.
+    #[test]
+    fn reload_swaps_context_and_keeps_previous_on_failure() {
+        crate::logging::init_for_tests();
+        let dir = std::env::temp_dir().join(format!("tws-reload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("first.typ"), "First").unwrap();
+
+        let handle = ContextHandle::from_directory(&dir).unwrap();
+        let before = handle.load();
+        assert_eq!(before.template_names(), vec!["first.typ"]);
+
+        std::fs::write(dir.join("second.typ"), "Second").unwrap();
+        handle.reload().unwrap();
+        assert_eq!(
+            handle.load().template_names(),
+            vec!["first.typ", "second.typ"]
+        );
+        assert_eq!(before.template_names(), vec!["first.typ"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(handle.reload().is_err());
+        assert_eq!(
+            handle.load().template_names(),
+            vec!["first.typ", "second.typ"]
+        );
+    }
+
+    /// The watcher reloads the context after files in its directory change.
+    #[cfg(feature = "hot-reload")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn watch_reloads_on_change() {
+        use std::time::{Duration, Instant};
+
+        crate::logging::init_for_tests();
+        let dir = std::env::temp_dir().join(format!("tws-watch-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("first.typ"), "First").unwrap();
+
+        let handle = ContextHandle::from_directory(&dir).unwrap();
+        let _watcher = handle.watch(Duration::from_millis(50)).unwrap();
+        std::fs::write(dir.join("second.typ"), "Second").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while handle.load().template_names().len() < 2 {
+            assert!(Instant::now() < deadline, "context was not reloaded");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(
+            handle.load().template_names(),
+            vec!["first.typ", "second.typ"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A reload requested while another one runs fails instead of waiting.
+    #[test]
+    fn try_reload_refuses_concurrent_reloads() {
+        crate::logging::init_for_tests();
+        let dir = std::env::temp_dir().join(format!("tws-reload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("first.typ"), "First").unwrap();
+        let handle = ContextHandle::from_directory(&dir).unwrap();
+
+        let reloading = handle.origin.as_deref().unwrap().reloading.lock().unwrap();
+        assert!(matches!(
+            handle.try_reload(),
+            Err(AppError::ReloadInProgress)
+        ));
+        drop(reloading);
+        handle.try_reload().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Contexts built in memory have no directory to reload from.
+    #[test]
+    fn reload_requires_directory() {
+        let handle = ContextHandle::from(PdfContext::from_assets(&[]).unwrap());
+        assert!(matches!(handle.reload(), Err(AppError::ReloadUnavailable)));
+    }
+}
@@ -0,0 +1,129 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use jsonschema::Validator;
+use serde::Serialize;
+use tracing::debug;
+use typst::{
+    foundations::Bytes,
+    syntax::{FileId, Source, VirtualRoot},
+};
+
+use crate::{
+    catalog::{TEMPLATE_SCHEMA_EXTENSION, read_sidecar},
+    error::{AppError, AppResult},
+};
+
+/// One way in which a render input does not match its template's schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaViolation {
+    /// JSON pointer to the offending value in the input; empty for the root.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "(root)"
+        } else {
+            &self.path
+        };
+        write!(f, "{path}: {}", self.message)
+    }
+}
+
+/// Compile the `.schema.json` sidecars of all templates, keyed by the
+/// template's file id.
+pub(crate) fn load_schemas(
+    sources: &[Source],
+    assets: &HashMap<FileId, Bytes>,
+) -> AppResult<HashMap<FileId, Validator>> {
+    let mut schemas = HashMap::new();
+    for source in sources {
+        let id = source.id();
+        if !matches!(id.root(), VirtualRoot::Project) {
+            continue;
+        }
+        let path = id.vpath().get_without_slash();
+        let Some(schema) =
+            read_sidecar::<serde_json::Value>(path, TEMPLATE_SCHEMA_EXTENSION, assets)?
+        else {
+            continue;
+        };
+
+        let validator =
+            jsonschema::validator_for(&schema).map_err(|error| AppError::TemplateSchema {
+                file: Path::new(path)
+                    .with_extension(TEMPLATE_SCHEMA_EXTENSION)
+                    .display()
+                    .to_string(),
+                message: error.to_string(),
+            })?;
+        debug!(template = path, "Loaded input schema");
+        schemas.insert(id, validator);
+    }
+    Ok(schemas)
+}
+
+/// Check `input` against `validator`, collecting every violation.
+pub(crate) fn validate(validator: &Validator, input: &serde_json::Value) -> AppResult<()> {
+    let violations = validator
+        .iter_errors(input)
+        .map(|error| SchemaViolation {
+            path: error.instance_path().as_str().to_owned(),
+            message: error.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::InputValidation(violations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SchemaViolation, validate};
+    use crate::{PdfContext, error::AppError};
+
+    /// Every violation is reported with a pointer into the input.
+    #[test]
+    fn validate_reports_all_violations() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["customer"],
+            "properties": {"items": {"type": "array", "items": {"type": "number"}}}
+        });
+        let validator = jsonschema::validator_for(&schema).unwrap();
+
+        assert!(validate(&validator, &serde_json::json!({"customer": "ACME"})).is_ok());
+
+        let Err(AppError::InputValidation(violations)) =
+            validate(&validator, &serde_json::json!({"items": [1, "two"]}))
+        else {
+            panic!("expected a validation error");
+        };
+        let paths = violations
+            .iter()
+            .map(|violation| violation.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.contains(&""));
+        assert!(paths.contains(&"/items/1"));
+
+        let root = SchemaViolation {
+            path: String::new(),
+            message: "oops".into(),
+        };
+        assert_eq!(root.to_string(), "(root): oops");
+    }
+
+    /// A sidecar that is not a valid schema fails the load instead of every render.
+    #[test]
+    fn invalid_schema_fails_the_load() {
+        let result =
+            PdfContext::from_assets(&[("doc.typ", b""), ("doc.schema.json", br#"{"type": 5}"#)]);
+        assert!(matches!(result, Err(AppError::TemplateSchema { .. })));
+    }
+}
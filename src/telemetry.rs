@@ -0,0 +1,309 @@
+use std::time::Duration;
+
+use metrics::{counter, gauge, histogram};
+
+use crate::{error::AppError, pdf::PdfContext};
+
+/// Template label used for names that are not in the context, so requests for
+/// arbitrary names cannot blow up the number of series.
+const UNKNOWN_TEMPLATE: &str = "unknown";
+
+/// Records the metrics of a single render.
+///
+/// Creating it counts the render and adds it to the in-flight gauge until it
+/// is dropped; [`RenderMetrics::finish`] counts failures by error kind.
+pub(crate) struct RenderMetrics {
+    template: String,
+    format: &'static str,
+}
+
+impl RenderMetrics {
+    pub(crate) fn start(context: &PdfContext, template: &str, format: &'static str) -> Self {
+        let template = template_label(context, template);
+        counter!("tws_renders_total", "template" => template.clone(), "format" => format)
+            .increment(1);
+        gauge!("tws_renders_in_flight").increment(1.0);
+        Self { template, format }
+    }
+
+    /// Record how long Typst took to compile the document.
+    pub(crate) fn compiled(&self, elapsed: Duration) {
+        histogram!("tws_compile_duration_seconds", "template" => self.template.clone())
+            .record(elapsed);
+    }
+
+    /// Record how long the export took and how large its output is.
+    pub(crate) fn exported(&self, elapsed: Duration, bytes: usize) {
+        let labels = [
+            ("template", self.template.clone()),
+            ("format", self.format.to_string()),
+        ];
+        histogram!("tws_export_duration_seconds", &labels).record(elapsed);
+        histogram!("tws_output_bytes", &labels).record(bytes as f64);
+    }
+
+    /// Count a warning about a font family that no font provides.
+    pub(crate) fn unknown_font(&self) {
+        counter!("tws_unknown_font_warnings_total", "template" => self.template.clone())
+            .increment(1);
+    }
+
+    /// Count the render as failed if `result` is an error, and pass it on.
+    ///
+    /// Cancelled renders are not counted: they were cancelled because their
+    /// caller gave up, and a caller that timed out counts the timeout itself
+    /// (see `RenderTimeouts`).
+    pub(crate) fn finish<T>(self, result: Result<T, AppError>) -> Result<T, AppError> {
+        if let Err(error) = &result
+            && !matches!(error, AppError::RenderCancelled)
+        {
+            count_failure(self.template.clone(), self.format, error);
+        }
+        result
+    }
+}
+
+impl Drop for RenderMetrics {
+    fn drop(&mut self) {
+        gauge!("tws_renders_in_flight").decrement(1.0);
+    }
+}
+
+/// Counts renders whose caller stopped waiting at the time limit, which the
+/// render itself never sees if it was still queued for a limiter slot or is
+/// cancelled before noticing its own deadline.
+#[cfg(feature = "server")]
+pub(crate) struct RenderTimeouts {
+    template: String,
+    format: &'static str,
+}
+
+#[cfg(feature = "server")]
+impl RenderTimeouts {
+    pub(crate) fn new(context: &PdfContext, template: &str, format: &'static str) -> Self {
+        Self {
+            template: template_label(context, template),
+            format,
+        }
+    }
+
+    /// Count the render as failed with `error`.
+    pub(crate) fn timed_out(self, error: &AppError) {
+        count_failure(self.template, self.format, error);
+    }
+}
+
+/// The label for `template`, or [`UNKNOWN_TEMPLATE`] if it does not exist.
+fn template_label(context: &PdfContext, template: &str) -> String {
+    if context.has_template(template) {
+        template.to_string()
+    } else {
+        UNKNOWN_TEMPLATE.to_string()
+    }
+}
+
+fn count_failure(template: String, format: &'static str, error: &AppError) {
+    counter!(
+        "tws_render_failures_total",
+        "template" => template,
+        "format" => format,
+        "error" => error.kind(),
+    )
+    .increment(1);
+}
+
+/// Wrap `task` for Tokio's blocking pool so the blocking task gauges count it
+/// from now until it returns.
+///
+/// `tws_blocking_tasks` counts every task submitted and not yet finished, and
+/// `tws_blocking_tasks_queued` those still waiting for a blocking thread, so
+/// a saturated pool shows even without a render limiter.
+pub(crate) fn blocking<F, T>(task: F) -> impl FnOnce() -> T + Send + 'static
+where
+    F: FnOnce() -> T + Send + 'static,
+{
+    let mut tracked = BlockingTask::submit();
+    move || {
+        tracked.start();
+        task()
+    }
+}
+
+/// Keeps a blocking task in the gauges until it is dropped.
+struct BlockingTask {
+    started: bool,
+}
+
+impl BlockingTask {
+    fn submit() -> Self {
+        gauge!("tws_blocking_tasks").increment(1.0);
+        gauge!("tws_blocking_tasks_queued").increment(1.0);
+        Self { started: false }
+    }
+
+    fn start(&mut self) {
+        gauge!("tws_blocking_tasks_queued").decrement(1.0);
+        self.started = true;
+    }
+}
+
+impl Drop for BlockingTask {
+    fn drop(&mut self) {
+        if !self.started {
+            gauge!("tws_blocking_tasks_queued").decrement(1.0);
+        }
+        gauge!("tws_blocking_tasks").decrement(1.0);
+    }
+}
+
+/// Record the number of items in a batch.
+pub(crate) fn record_batch(items: usize) {
+    histogram!("tws_batch_items").record(items as f64);
+}
+
+/// Keeps a render in the gauge of renders waiting for a limiter slot.
+pub(crate) struct WaitingRender;
+
+impl WaitingRender {
+    pub(crate) fn start() -> Self {
+        gauge!("tws_renders_waiting").increment(1.0);
+        Self
+    }
+
+    /// Record how long the render waited once it got its slot.
+    pub(crate) fn acquired(self, waited: Duration) {
+        histogram!("tws_render_wait_seconds").record(waited);
+    }
+}
+
+impl Drop for WaitingRender {
+    fn drop(&mut self) {
+        gauge!("tws_renders_waiting").decrement(1.0);
+    }
+}
+
+#[cfg(feature = "server")]
+pub(crate) use abandoned::track_render;
+
+#[cfg(feature = "server")]
+mod abandoned {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU8, Ordering},
+    };
+
+    use metrics::{counter, gauge};
+
+    const RENDER_RUNNING: u8 = 0;
+    const RENDER_FINISHED: u8 = 1;
+    const RENDER_ABANDONED: u8 = 2;
+
+    /// Track a blocking render whose caller may stop waiting for it.
+    ///
+    /// Typst only notices cancellation at its next file access, so a render
+    /// can keep running (and keep its limiter slot) after its request timed
+    /// out or its client disconnected. Dropping the [`RenderCaller`] while
+    /// the [`RenderWorker`] is still alive counts the render as abandoned
+    /// until the worker is dropped too.
+    pub(crate) fn track_render() -> (RenderCaller, RenderWorker) {
+        let state = Arc::new(AtomicU8::new(RENDER_RUNNING));
+        (RenderCaller(Arc::clone(&state)), RenderWorker(state))
+    }
+
+    /// Held by the future waiting for a render.
+    pub(crate) struct RenderCaller(Arc<AtomicU8>);
+
+    impl Drop for RenderCaller {
+        fn drop(&mut self) {
+            let abandoned = self
+                .0
+                .compare_exchange(
+                    RENDER_RUNNING,
+                    RENDER_ABANDONED,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok();
+            if abandoned {
+                counter!("tws_renders_abandoned_total").increment(1);
+                gauge!("tws_renders_abandoned").increment(1.0);
+            }
+        }
+    }
+
+    /// Held by the blocking task doing the render.
+    pub(crate) struct RenderWorker(Arc<AtomicU8>);
+
+    impl Drop for RenderWorker {
+        fn drop(&mut self) {
+            if self.0.swap(RENDER_FINISHED, Ordering::SeqCst) == RENDER_ABANDONED {
+                gauge!("tws_renders_abandoned").decrement(1.0);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+pub(crate) use prometheus::prometheus_handle;
+
+#[cfg(feature = "server")]
+mod prometheus {
+    use std::{sync::OnceLock, time::Duration};
+
+    use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+    use tracing::warn;
+
+    /// How often histogram buffers are drained when nothing scrapes them.
+    const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+    const DURATION_BUCKETS: &[f64] = &[
+        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+    ];
+    const SIZE_BUCKETS: &[f64] = &[
+        1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+    ];
+    const BATCH_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0];
+
+    /// Install the Prometheus recorder on first use and return its handle.
+    ///
+    /// Metrics recorded before the recorder is installed are dropped, so the
+    /// server calls this while building its router. Returns `None` when the
+    /// application already installed a different global recorder; the
+    /// metrics then go there instead.
+    pub(crate) fn prometheus_handle() -> Option<PrometheusHandle> {
+        static HANDLE: OnceLock<Option<PrometheusHandle>> = OnceLock::new();
+        HANDLE.get_or_init(install).clone()
+    }
+
+    fn install() -> Option<PrometheusHandle> {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Suffix("_seconds".into()), DURATION_BUCKETS)
+            .and_then(|builder| {
+                builder.set_buckets_for_metric(Matcher::Suffix("_bytes".into()), SIZE_BUCKETS)
+            })
+            .and_then(|builder| {
+                builder
+                    .set_buckets_for_metric(Matcher::Full("tws_batch_items".into()), BATCH_BUCKETS)
+            })
+            .expect("bucket lists are not empty")
+            .build_recorder();
+        let handle = recorder.handle();
+
+        if let Err(error) = metrics::set_global_recorder(recorder) {
+            warn!(%error, "A metrics recorder is already installed; not serving /metrics");
+            return None;
+        }
+
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let upkeep = handle.clone();
+            runtime.spawn(async move {
+                let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    upkeep.run_upkeep();
+                }
+            });
+        }
+        Some(handle)
+    }
+}
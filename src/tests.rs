@@ -1,26 +1,26 @@
-use std::{io::Read, sync::Arc};
+use std::io::Read;
 
 use axum::{
     Router,
     body::{self, Body},
     http::{Request, StatusCode},
-    routing::{get, post},
 };
 use tower::util::ServiceExt;
 
-use crate::{CRATE_INFO, handlers, logging, pdf::PdfContext};
+use crate::{
+    CRATE_INFO, ContextHandle, DiagnosticsMode, PdfContext, RenderLimiter, ServerOptions, logging,
+    server,
+};
 
 /// Construct an Axum router wired with the application's routes for testing.
 fn build_router() -> Router {
-    let context = Arc::new(PdfContext::from_directory("./assets").unwrap());
-    Router::new()
-        .route("/", get(handlers::root))
-        .route(
-            "/render-pdf/{template}/{file_name}",
-            get(handlers::render_pdf),
-        )
-        .route("/render-pdf/batch", post(handlers::render_pdf_batch))
-        .with_state(context)
+    build_router_with_options(&ServerOptions::default())
+}
+
+/// Construct the application router using explicit server options.
+fn build_router_with_options(options: &ServerOptions) -> Router {
+    let context = ContextHandle::from_directory("./assets").unwrap();
+    server::router(context, options)
 }
 
 #[tokio::test]
@@ -90,86 +90,94 @@ async fn render_pdf_success() {
 }
 
 #[tokio::test]
-/// Ensure the batch endpoint renders a ZIP archive containing multiple PDFs.
-async fn render_pdf_batch_success() {
+/// Verify that the single render route also accepts POST with the same payload.
+async fn render_pdf_post_success() {
     logging::init_for_tests();
     let router = build_router();
 
-    let payload = serde_json::json!([
-        {
-            "template": "example.typ",
-            "file_name": "first.pdf",
-            "input": { "name": "Batch One", "list": ["Item"] }
-        },
-        {
-            "template": "example.typ",
-            "file_name": "second.pdf",
-            "input": { "name": "Batch Two", "list": ["Item"] }
-        }
-    ]);
-
     let response = router
-        .clone()
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/render-pdf/batch")
+                .uri("/render-pdf/example.typ/output.pdf")
                 .header("content-type", "application/json")
-                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
                 .unwrap(),
         )
         .await
         .unwrap();
 
     assert_eq!(response.status(), StatusCode::OK);
-    let headers = response.headers();
     assert_eq!(
-        headers.get(axum::http::header::CONTENT_TYPE).unwrap(),
-        "application/zip"
+        response
+            .headers()
+            .get(axum::http::header::CONTENT_TYPE)
+            .unwrap(),
+        "application/pdf"
     );
+    let body = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+        .await
+        .unwrap();
+    assert!(body.starts_with(b"%PDF"), "expected PDF body");
+}
+
+#[tokio::test]
+/// Verify that a JSON envelope carrying template, file name and input renders a PDF.
+async fn render_pdf_envelope_success() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let payload = serde_json::json!({
+        "template": "example.typ",
+        "file_name": "envelope.pdf",
+        "input": { "name": "Envelope", "list": ["Item"] }
+    });
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
     assert!(
-        headers
+        response
+            .headers()
             .get(axum::http::header::CONTENT_DISPOSITION)
             .unwrap()
             .to_str()
             .unwrap()
-            .contains(".zip")
+            .contains("envelope.pdf")
     );
-
-    let bytes = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+    let body = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
         .await
         .unwrap();
-    assert!(!bytes.is_empty(), "expected ZIP body to be non-empty");
-
-    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
-    assert_eq!(archive.len(), 2);
-
-    for name in ["first.pdf", "second.pdf"] {
-        let mut file = archive.by_name(name).unwrap();
-        let mut content = Vec::new();
-        file.read_to_end(&mut content).unwrap();
-        assert!(!content.is_empty(), "expected {name} to contain PDF data");
-    }
+    assert!(body.starts_with(b"%PDF"), "expected PDF body");
 }
 
 #[tokio::test]
-/// Confirm the batch endpoint propagates a 404 when any template is missing.
-async fn render_pdf_batch_missing_template() {
+/// Confirm the envelope route returns 404 for missing templates.
+async fn render_pdf_envelope_missing_template() {
     logging::init_for_tests();
     let router = build_router();
 
-    let payload = serde_json::json!([{
+    let payload = serde_json::json!({
         "template": "unknown.typ",
         "file_name": "missing.pdf",
-        "input": { "name": "World" }
-    }]);
+        "input": {}
+    });
 
     let response = router
-        .clone()
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/render-pdf/batch")
+                .uri("/render-pdf")
                 .header("content-type", "application/json")
                 .body(Body::from(serde_json::to_vec(&payload).unwrap()))
                 .unwrap(),
@@ -178,17 +186,75 @@ async fn render_pdf_batch_missing_template() {
         .unwrap();
 
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
-    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+}
+
+#[tokio::test]
+/// File names that cannot go into `Content-Disposition` unchanged are
+/// rejected as bad requests, whether they come from an envelope or the path.
+async fn render_pdf_rejects_unsafe_file_names() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let payload = serde_json::json!({
+        "template": "example.typ",
+        "file_name": "evil\r\nSet-Cookie: x.pdf",
+        "input": { "name": "Envelope", "list": ["Item"] }
+    });
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
         .await
         .unwrap();
-    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
-    assert_eq!(json.get("error").unwrap(), "Requested template not found");
-    assert!(json.get("reference").is_some());
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-png/example.typ/evil%0D%0A.png?page=1")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // A quote would end the quoted file name and let the rest pass as
+    // further header parameters.
+    for file_name in ["a\"; filename*=UTF-8''x.pdf", "a\\b.pdf"] {
+        let payload = serde_json::json!({
+            "template": "example.typ",
+            "file_name": file_name,
+            "input": { "name": "Envelope", "list": ["Item"] }
+        });
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/render-pdf")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{file_name}");
+    }
 }
 
 #[tokio::test]
-/// Confirm single render requests return 404 for missing templates.
-async fn render_pdf_missing_template() {
+/// Verify that requesting a single page returns a PNG image.
+async fn render_png_single_page() {
     logging::init_for_tests();
     let router = build_router();
 
@@ -197,26 +263,32 @@ async fn render_pdf_missing_template() {
         .oneshot(
             Request::builder()
                 .method("GET")
-                .uri("/render-pdf/unknown.typ/output.pdf")
+                .uri("/render-png/example.typ/preview.png?page=1&pixel_per_pt=1")
                 .header("content-type", "application/json")
-                .body(Body::from(r#"{"name":"World"}"#))
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
                 .unwrap(),
         )
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::NOT_FOUND);
-    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get(axum::http::header::CONTENT_TYPE)
+            .unwrap(),
+        "image/png"
+    );
+
+    let body = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
         .await
         .unwrap();
-    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
-    assert_eq!(json.get("error").unwrap(), "Requested template not found");
-    assert!(json.get("reference").is_some());
+    assert!(body.starts_with(b"\x89PNG"), "expected PNG signature");
 }
 
 #[tokio::test]
-/// Ensure malformed JSON payloads produce a 400 Bad Request response.
-async fn render_pdf_invalid_json() {
+/// Verify that omitting the page returns every page as a ZIP archive of PNGs.
+async fn render_png_all_pages_zip() {
     logging::init_for_tests();
     let router = build_router();
 
@@ -225,9 +297,48 @@ async fn render_pdf_invalid_json() {
         .oneshot(
             Request::builder()
                 .method("GET")
-                .uri("/render-pdf/example.typ/output.pdf")
+                .uri("/render-png/example.typ/preview.zip")
                 .header("content-type", "application/json")
-                .body(Body::from("{"))
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get(axum::http::header::CONTENT_TYPE)
+            .unwrap(),
+        "application/zip"
+    );
+
+    let bytes = body::to_bytes(response.into_body(), 50 * 1024 * 1024)
+        .await
+        .unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    assert!(!archive.is_empty());
+    let mut file = archive.by_name("preview-1.png").unwrap();
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).unwrap();
+    assert!(content.starts_with(b"\x89PNG"), "expected PNG signature");
+}
+
+#[tokio::test]
+/// Ensure requesting a page past the end of the document yields a 400.
+async fn render_png_page_out_of_range() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/render-png/example.typ/preview.png?page=999")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
                 .unwrap(),
         )
         .await
@@ -237,12 +348,13 @@ async fn render_pdf_invalid_json() {
     let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
         .await
         .unwrap();
-    assert!(!bytes.is_empty());
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json.get("error").unwrap(), "Requested page does not exist");
 }
 
 #[tokio::test]
-/// Ensure incorrectly structured JSON payloads produce a 400 Bad Request response.
-async fn render_pdf_invalid_json_structure() {
+/// Verify that the SVG endpoint returns a merged SVG document by default.
+async fn render_svg_merged() {
     logging::init_for_tests();
     let router = build_router();
 
@@ -251,18 +363,1238 @@ async fn render_pdf_invalid_json_structure() {
         .oneshot(
             Request::builder()
                 .method("GET")
-                .uri("/render-pdf/example.typ/output.pdf")
+                .uri("/render-svg/example.typ/preview.svg")
                 .header("content-type", "application/json")
-                .body(Body::from(r#"{"world":"Name","list":["Item"]}"#))
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
                 .unwrap(),
         )
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-    let body_bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response
+            .headers()
+            .get(axum::http::header::CONTENT_TYPE)
+            .unwrap(),
+        "image/svg+xml"
+    );
+
+    let body = body::to_bytes(response.into_body(), 50 * 1024 * 1024)
         .await
         .unwrap();
-    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
-    assert_eq!(body.get("error").unwrap(), "Document compilation failed");
+    let text = std::str::from_utf8(&body).unwrap();
+    assert!(text.contains("<svg"), "expected SVG markup");
+}
+
+#[tokio::test]
+/// Verify that disabling merging returns one SVG per page in a ZIP archive.
+async fn render_svg_per_page_zip() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/render-svg/example.typ/preview.zip?merge_pages=false")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = body::to_bytes(response.into_body(), 50 * 1024 * 1024)
+        .await
+        .unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    let mut file = archive.by_name("preview-1.svg").unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).unwrap();
+    assert!(content.contains("<svg"), "expected SVG markup");
+}
+
+#[tokio::test]
+/// Ensure the batch endpoint renders a ZIP archive containing multiple PDFs.
+async fn render_pdf_batch_success() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let payload = serde_json::json!([
+        {
+            "template": "example.typ",
+            "file_name": "first.pdf",
+            "input": { "name": "Batch One", "list": ["Item"] }
+        },
+        {
+            "template": "example.typ",
+            "file_name": "second.pdf",
+            "input": { "name": "Batch Two", "list": ["Item"] }
+        }
+    ]);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf/batch")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(
+        headers.get(axum::http::header::CONTENT_TYPE).unwrap(),
+        "application/zip"
+    );
+    assert!(
+        headers
+            .get(axum::http::header::CONTENT_DISPOSITION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains(".zip")
+    );
+
+    let bytes = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+        .await
+        .unwrap();
+    assert!(!bytes.is_empty(), "expected ZIP body to be non-empty");
+
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    assert_eq!(archive.len(), 2);
+
+    for name in ["first.pdf", "second.pdf"] {
+        let mut file = archive.by_name(name).unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        assert!(!content.is_empty(), "expected {name} to contain PDF data");
+    }
+}
+
+#[tokio::test]
+/// Batch renders with `output=pdf` are merged into a single PDF download.
+async fn render_pdf_batch_merges_into_single_pdf() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let payload = serde_json::json!([
+        {
+            "template": "example.typ",
+            "file_name": "first.pdf",
+            "input": { "name": "First", "list": ["Item"] }
+        },
+        {
+            "template": "example.typ",
+            "file_name": "second.pdf",
+            "input": { "name": "Second", "list": ["Item"] }
+        }
+    ]);
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf/batch?output=pdf")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "application/pdf"
+    );
+
+    let bytes = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+        .await
+        .unwrap();
+    assert!(bytes.starts_with(b"%PDF"));
+}
+
+#[tokio::test]
+/// With `?errors=report` a failing item is listed in the manifest instead of
+/// truncating the archive; diagnostics stay hidden by default.
+async fn render_pdf_batch_reports_item_errors() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let payload = serde_json::json!([
+        {
+            "template": "example.typ",
+            "file_name": "ok.pdf",
+            "input": { "name": "Fine", "list": ["Item"] }
+        },
+        {
+            "template": "example.typ",
+            "file_name": "broken.pdf",
+            "input": { "name": "Broken", "list": ["Item"] },
+            "pdf": { "page_ranges": "not-a-range" }
+        }
+    ]);
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf/batch?errors=report")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+        .await
+        .unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    assert!(archive.by_name("ok.pdf").is_ok());
+    assert!(archive.by_name("broken.pdf").is_err());
+
+    let mut manifest = String::new();
+    archive
+        .by_name("manifest.json")
+        .unwrap()
+        .read_to_string(&mut manifest)
+        .unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(manifest["items"][0]["status"], "ok");
+    assert_eq!(manifest["items"][1]["status"], "error");
+    assert!(manifest["items"][1].get("diagnostics").is_none());
+}
+
+#[tokio::test]
+/// Confirm the batch endpoint propagates a 404 when any template is missing.
+async fn render_pdf_batch_missing_template() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let payload = serde_json::json!([{
+        "template": "unknown.typ",
+        "file_name": "missing.pdf",
+        "input": { "name": "World" }
+    }]);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf/batch")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json.get("error").unwrap(), "Requested template not found");
+    assert!(json.get("reference").is_some());
+}
+
+#[tokio::test]
+/// Confirm single render requests return 404 for missing templates.
+async fn render_pdf_missing_template() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/render-pdf/unknown.typ/output.pdf")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"World"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json.get("error").unwrap(), "Requested template not found");
+    assert!(json.get("reference").is_some());
+}
+
+#[tokio::test]
+/// Ensure malformed JSON payloads produce a 400 Bad Request response.
+async fn render_pdf_invalid_json() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/render-pdf/example.typ/output.pdf")
+                .header("content-type", "application/json")
+                .body(Body::from("{"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    assert!(!bytes.is_empty());
+}
+
+#[tokio::test]
+/// Ensure incorrectly structured JSON payloads produce a 400 Bad Request response.
+async fn render_pdf_invalid_json_structure() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/render-pdf/example.typ/output.pdf")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"world":"Name","list":["Item"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body_bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body_bytes).unwrap();
+    assert_eq!(body.get("error").unwrap(), "Document compilation failed");
+}
+
+/// Send a request whose input does not match the template so compilation fails.
+async fn send_failing_render(router: Router, diagnostics_header: bool) -> serde_json::Value {
+    let mut request = Request::builder()
+        .method("GET")
+        .uri("/render-pdf/example.typ/output.pdf")
+        .header("content-type", "application/json");
+    if diagnostics_header {
+        request = request.header("x-typst-diagnostics", "true");
+    }
+
+    let response = router
+        .oneshot(
+            request
+                .body(Body::from(r#"{"world":"Name","list":["Item"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body_bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    serde_json::from_slice(&body_bytes).unwrap()
+}
+
+#[tokio::test]
+/// Diagnostics stay hidden by default, even when the request asks for them.
+async fn diagnostics_hidden_by_default() {
+    logging::init_for_tests();
+
+    let body = send_failing_render(build_router(), true).await;
+    assert_eq!(body.get("error").unwrap(), "Document compilation failed");
+    assert!(body.get("diagnostics").is_none());
+}
+
+#[tokio::test]
+/// In on-request mode, diagnostics are only returned when the header is set.
+async fn diagnostics_exposed_on_request() {
+    logging::init_for_tests();
+    let router = build_router_with_options(&ServerOptions {
+        diagnostics: DiagnosticsMode::OnRequest,
+        ..Default::default()
+    });
+
+    let body = send_failing_render(router.clone(), false).await;
+    assert!(body.get("diagnostics").is_none());
+
+    let body = send_failing_render(router, true).await;
+    assert_eq!(body.get("error").unwrap(), "Document compilation failed");
+    assert!(body.get("reference").is_some());
+
+    let diagnostics = body.get("diagnostics").unwrap().as_array().unwrap();
+    assert!(!diagnostics.is_empty());
+    let first = &diagnostics[0];
+    assert_eq!(first.get("severity").unwrap(), "error");
+    assert!(first.get("message").unwrap().as_str().is_some());
+    let location = first.get("location").unwrap();
+    assert_eq!(location.get("file").unwrap(), "example.typ");
+    assert!(location.get("line").unwrap().as_u64().unwrap() > 1);
+    assert!(location.get("column").unwrap().as_u64().unwrap() >= 1);
+}
+
+#[tokio::test]
+/// In always mode, diagnostics are returned without any request header.
+async fn diagnostics_always_exposed() {
+    logging::init_for_tests();
+    let router = build_router_with_options(&ServerOptions {
+        diagnostics: DiagnosticsMode::Always,
+        ..Default::default()
+    });
+
+    let body = send_failing_render(router, false).await;
+    assert!(body.get("diagnostics").unwrap().as_array().is_some());
+}
+
+#[tokio::test]
+/// Ensure the reload endpoint rebuilds the context and reports its templates.
+async fn reload_endpoint_reports_templates() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/reload")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(
+        json.get("templates").unwrap(),
+        &serde_json::json!(["example.typ"])
+    );
+}
+
+#[tokio::test]
+/// Ensure a render exceeding the per-request time limit returns a 504.
+async fn render_pdf_timeout_header() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/render-pdf/example.typ/output.pdf")
+                .header("content-type", "application/json")
+                .header("x-render-timeout-ms", "0")
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json.get("error").unwrap(), "Render exceeded its time limit");
+}
+
+/// Render `uri` with a template asserting `sys.inputs.name == "World"`,
+/// passing `inputs` in the `x-typst-inputs` header if given.
+async fn render_with_inputs_header(uri: &str, inputs: Option<&str>) -> StatusCode {
+    let template: &[u8] = b"#assert.eq(sys.inputs.at(\"name\", default: none), \"World\")";
+    let context = PdfContext::from_assets(&[("inputs.typ", template)]).unwrap();
+    let router = server::router(context.into(), &ServerOptions::default());
+
+    let mut request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("content-type", "application/json");
+    if let Some(inputs) = inputs {
+        request = request.header("x-typst-inputs", inputs);
+    }
+    router
+        .oneshot(request.body(Body::from("{}")).unwrap())
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+/// The PDF route exposes the inputs header as `sys.inputs`.
+async fn render_pdf_accepts_inputs_header() {
+    logging::init_for_tests();
+    let uri = "/render-pdf/inputs.typ/out.pdf";
+    let inputs = Some(r#"{"name": "World"}"#);
+    assert_eq!(render_with_inputs_header(uri, inputs).await, StatusCode::OK);
+    assert_eq!(
+        render_with_inputs_header(uri, None).await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        render_with_inputs_header(uri, Some(r#"{"name": 1}"#)).await,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+/// The PNG route exposes the inputs header as `sys.inputs`.
+async fn render_png_accepts_inputs_header() {
+    logging::init_for_tests();
+    let uri = "/render-png/inputs.typ/out.png?page=1";
+    let inputs = Some(r#"{"name": "World"}"#);
+    assert_eq!(render_with_inputs_header(uri, inputs).await, StatusCode::OK);
+    assert_eq!(
+        render_with_inputs_header(uri, None).await,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+/// The SVG route exposes the inputs header as `sys.inputs`.
+async fn render_svg_accepts_inputs_header() {
+    logging::init_for_tests();
+    let uri = "/render-svg/inputs.typ/out.svg";
+    let inputs = Some(r#"{"name": "World"}"#);
+    assert_eq!(render_with_inputs_header(uri, inputs).await, StatusCode::OK);
+    assert_eq!(
+        render_with_inputs_header(uri, None).await,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+/// The time limit header can lower the server's limit but not raise it.
+async fn render_pdf_timeout_header_is_capped_by_server() {
+    logging::init_for_tests();
+    let router = build_router_with_options(&ServerOptions {
+        render_timeout: Some(std::time::Duration::ZERO),
+        ..Default::default()
+    });
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/render-pdf/example.typ/output.pdf")
+                .header("content-type", "application/json")
+                .header("x-render-timeout-ms", "600000")
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+    // The same goes for the time limit of an envelope.
+    let payload = serde_json::json!({
+        "template": "example.typ",
+        "file_name": "output.pdf",
+        "input": { "name": "World", "list": ["Test"] },
+        "timeout_ms": 600000
+    });
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+}
+
+#[tokio::test]
+/// Ensure a malformed time limit header is rejected.
+async fn render_pdf_invalid_timeout_header() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("GET")
+                .uri("/render-pdf/example.typ/output.pdf")
+                .header("content-type", "application/json")
+                .header("x-render-timeout-ms", "soon")
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+/// PDF options can be passed as query parameters and are validated.
+async fn render_pdf_accepts_pdf_options_query() {
+    logging::init_for_tests();
+
+    let payload = serde_json::json!({ "name": "Options", "list": ["Item"] });
+    let request = |uri: &str| {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+            .unwrap()
+    };
+
+    let response = build_router()
+        .oneshot(request(
+            "/render-pdf/example.typ/doc.pdf?standards=2.0&timestamp=2024-01-02T03:04:05Z",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+        .await
+        .unwrap();
+    assert!(body.starts_with(b"%PDF-2.0"), "expected a PDF 2.0 body");
+
+    let response = build_router()
+        .oneshot(request("/render-pdf/example.typ/doc.pdf?standards=a-9z"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+/// Pinning the render time via header yields byte-identical PDFs.
+async fn render_pdf_with_pinned_now_is_reproducible() {
+    logging::init_for_tests();
+
+    let mut bodies = Vec::new();
+    for _ in 0..2 {
+        let response = build_router()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/render-pdf/example.typ/pinned.pdf")
+                    .header("content-type", "application/json")
+                    .header("x-render-now", "2024-05-06T07:08:09Z")
+                    .body(Body::from(r#"{"name": "Pinned", "list": ["Item"]}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        bodies.push(
+            body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+                .await
+                .unwrap(),
+        );
+    }
+    assert_eq!(bodies[0], bodies[1]);
+}
+
+#[tokio::test]
+/// A job can be polled until it completes, downloaded, and deleted.
+async fn job_lifecycle() {
+    logging::init_for_tests();
+    let directory = std::env::temp_dir().join(format!("tws-jobs-{}", uuid::Uuid::new_v4()));
+    let router = build_router_with_options(&ServerOptions {
+        jobs: crate::JobOptions {
+            directory: directory.clone(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let payload = serde_json::json!([
+        {
+            "template": "example.typ",
+            "file_name": "first.pdf",
+            "input": { "name": "First", "list": ["Item"] }
+        }
+    ]);
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/jobs")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let location = response.headers()["location"].to_str().unwrap().to_string();
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let job: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(location, format!("/jobs/{}", job["id"].as_str().unwrap()));
+    assert_eq!(job["total"], 1);
+
+    let status = loop {
+        let response = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(&location)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+            .await
+            .unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        if status["state"] != "running" {
+            break status;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    };
+    assert_eq!(status["state"], "completed");
+    assert_eq!(status["completed"], 1);
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("{location}/result"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "application/zip"
+    );
+    let bytes = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+        .await
+        .unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+    assert!(archive.by_name("first.pdf").is_ok());
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri(&location)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .uri(&location)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(feature = "webhooks")]
+#[tokio::test]
+/// Job callbacks are retried until the receiver accepts them and carry an
+/// HMAC signature of the timestamp and body.
+async fn job_callback_is_retried_and_signed() {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use axum::{body::Bytes, extract::State, http::HeaderMap, routing::post};
+    use tokio::sync::mpsc;
+
+    logging::init_for_tests();
+
+    type Deliveries = mpsc::UnboundedSender<(HeaderMap, Bytes)>;
+
+    // Stand-in receiver rejecting the first delivery and accepting the rest.
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let receiver = Router::new()
+        .route(
+            "/hook",
+            post(
+                |State((tx, attempts)): State<(Deliveries, Arc<AtomicUsize>)>,
+                 headers: HeaderMap,
+                 body: Bytes| async move {
+                    tx.send((headers, body)).unwrap();
+                    if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                },
+            ),
+        )
+        .with_state((tx, Arc::new(AtomicUsize::new(0))));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+
+    let directory = std::env::temp_dir().join(format!("tws-jobs-{}", uuid::Uuid::new_v4()));
+    let router = build_router_with_options(&ServerOptions {
+        jobs: crate::JobOptions {
+            directory: directory.clone(),
+            webhook: crate::WebhookOptions {
+                secret: Some("secret".to_string()),
+                backoff: std::time::Duration::from_millis(10),
+                base_url: Some("https://pdf.example.com/".to_string()),
+                // The stand-in receiver listens on loopback.
+                allow_private: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let payload = serde_json::json!([
+        {
+            "template": "example.typ",
+            "file_name": "first.pdf",
+            "input": { "name": "First", "list": ["Item"] }
+        }
+    ]);
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/jobs?callback=http://{address}/hook"))
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let mut deliveries = Vec::new();
+    for _ in 0..2 {
+        let delivery = tokio::time::timeout(std::time::Duration::from_secs(30), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        deliveries.push(delivery);
+    }
+    let (headers, body) = &deliveries[1];
+    assert_eq!(deliveries[0].1, body);
+    let timestamp = headers[crate::webhook::TIMESTAMP_HEADER]
+        .to_str()
+        .unwrap()
+        .parse::<i64>()
+        .unwrap();
+    assert!((chrono::Utc::now().timestamp() - timestamp).abs() < 60);
+    assert_eq!(
+        headers[crate::webhook::SIGNATURE_HEADER],
+        crate::webhook::sign(b"secret", timestamp, body)
+    );
+
+    let notification: serde_json::Value = serde_json::from_slice(body).unwrap();
+    let id = notification["id"].as_str().unwrap();
+    assert_eq!(notification["state"], "completed");
+    assert_eq!(
+        notification["result"],
+        format!("https://pdf.example.com/jobs/{id}/result")
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+/// The liveness probe answers without touching the render pipeline.
+async fn healthz_reports_ok() {
+    logging::init_for_tests();
+    let response = build_router()
+        .oneshot(
+            Request::builder()
+                .uri("/healthz")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = body::to_bytes(response.into_body(), 1024).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["status"], "ok");
+}
+
+#[tokio::test]
+/// The template catalog lists each template with its sidecar metadata.
+async fn templates_lists_catalog() {
+    logging::init_for_tests();
+    let response = build_router()
+        .oneshot(
+            Request::builder()
+                .uri("/templates")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = body::to_bytes(response.into_body(), 64 * 1024)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let example = &body["templates"][0];
+    assert_eq!(example["name"], "example.typ");
+    assert_eq!(example["path"], "/example.typ");
+    assert!(example["size"].as_u64().unwrap() > 0);
+    assert!(example["modified"].is_string());
+    assert!(example["hash"].as_str().unwrap().starts_with("sha256:"));
+    assert!(example["description"].is_string());
+    assert_eq!(example["sample_input"]["name"], "World");
+}
+
+#[tokio::test]
+/// Input that violates the template's schema is rejected before compiling.
+async fn render_rejects_input_violating_schema() {
+    logging::init_for_tests();
+    let schema: &[u8] = br#"{
+        "type": "object",
+        "required": ["customer"],
+        "properties": {"total": {"type": "number"}}
+    }"#;
+    let context = PdfContext::from_assets(&[
+        ("invoice.typ", b"#json(\"input.json\").customer"),
+        ("invoice.schema.json", schema),
+    ])
+    .unwrap();
+    let router = server::router(context.into(), &ServerOptions::default());
+
+    let render = |input: serde_json::Value| {
+        router.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf/invoice.typ/invoice.pdf")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_vec(&input).unwrap()))
+                .unwrap(),
+        )
+    };
+
+    let response = render(serde_json::json!({"total": "ten"})).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let bytes = body::to_bytes(response.into_body(), 64 * 1024)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let paths = body["violations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|violation| violation["path"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(paths.len(), 2, "unexpected violations: {body}");
+    assert!(paths.contains(&""));
+    assert!(paths.contains(&"/total"));
+
+    let response = render(serde_json::json!({"customer": "ACME", "total": 10}))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+/// `/fonts` lists the fonts of the assets directory.
+async fn fonts_lists_available_faces() {
+    logging::init_for_tests();
+    let response = build_router()
+        .oneshot(
+            Request::builder()
+                .uri("/fonts")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let bagnard = body["fonts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|font| font["family"] == "Bagnard")
+        .expect("Bagnard should be listed");
+    assert_eq!(bagnard["source"], "project");
+    assert_eq!(bagnard["file"], "Bagnard.otf");
+    assert_eq!(bagnard["style"], "normal");
+    assert!(bagnard["coverage"]["codepoints"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+/// Font families no font provides are reported in a response header.
+async fn render_reports_unknown_fonts_header() {
+    logging::init_for_tests();
+    let template: &[u8] = b"#set text(font: \"Missing Sans\")\nHello";
+    let context = PdfContext::from_assets(&[("doc.typ", template)]).unwrap();
+    let router = server::router(context.into(), &ServerOptions::default());
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf/doc.typ/doc.pdf")
+                .header("content-type", "application/json")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-typst-unknown-fonts"], "missing sans");
+}
+
+/// Query `/readyz` and return its status and JSON body.
+async fn readiness(router: Router) -> (StatusCode, serde_json::Value) {
+    let response = router
+        .oneshot(
+            Request::builder()
+                .uri("/readyz")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = body::to_bytes(response.into_body(), 1024 * 1024)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+/// Readiness requires the configured smoke render to succeed.
+async fn readyz_runs_smoke_render() {
+    logging::init_for_tests();
+
+    let (status, body) = readiness(build_router()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["smoke_render"], "skipped");
+    assert_eq!(body["templates"], 1);
+
+    let router = build_router_with_options(&ServerOptions {
+        readiness: Some(crate::ReadinessCheck {
+            template: "example.typ".to_string(),
+            input: serde_json::json!({ "name": "Ready", "list": ["Item"] }),
+        }),
+        ..Default::default()
+    });
+    let (status, body) = readiness(router.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["smoke_render"], "ok");
+    // Answered from the cached result for the same context.
+    assert_eq!(readiness(router).await.0, StatusCode::OK);
+
+    let broken = |diagnostics| {
+        build_router_with_options(&ServerOptions {
+            readiness: Some(crate::ReadinessCheck {
+                template: "missing.typ".to_string(),
+                input: serde_json::json!({}),
+            }),
+            diagnostics,
+            ..Default::default()
+        })
+    };
+    let (status, body) = readiness(broken(DiagnosticsMode::Hidden)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["smoke_render"], "failed");
+    assert_eq!(body["error"], "Requested template not found");
+
+    // Details such as file names only appear when diagnostics are exposed.
+    let (_, body) = readiness(broken(DiagnosticsMode::Always)).await;
+    assert!(body["error"].as_str().unwrap().contains("missing.typ"));
+}
+
+#[tokio::test]
+/// A failed smoke render is reported from the cache instead of running again
+/// on every probe.
+async fn readyz_caches_smoke_render_failures() {
+    logging::init_for_tests();
+    let limiter = RenderLimiter::new(std::num::NonZeroUsize::MIN);
+    let router = build_router_with_options(&ServerOptions {
+        readiness: Some(crate::ReadinessCheck {
+            template: "example.typ".to_string(),
+            input: serde_json::json!({ "name": "Ready", "list": ["Item"] }),
+        }),
+        render_timeout: Some(std::time::Duration::from_millis(50)),
+        render_limiter: Some(limiter.clone()),
+        ..Default::default()
+    });
+
+    let busy = limiter.acquire().await;
+    let (status, body) = readiness(router.clone()).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"], "Render exceeded its time limit");
+
+    // The render would succeed now, but the failure is still cached.
+    drop(busy);
+    let (status, body) = readiness(router).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"], "Render exceeded its time limit");
+}
+
+#[tokio::test]
+/// Renders show up in the Prometheus metrics with their template label.
+async fn metrics_report_renders_per_template() {
+    logging::init_for_tests();
+    let router = build_router();
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-pdf/example.typ/metrics.pdf")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "name": "Metrics", "list": ["Item"] }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+        .await
+        .unwrap();
+    let metrics = String::from_utf8(bytes.to_vec()).unwrap();
+
+    for name in [
+        "tws_renders_total",
+        "tws_compile_duration_seconds_bucket",
+        "tws_export_duration_seconds_bucket",
+        "tws_output_bytes_bucket",
+    ] {
+        assert!(
+            metrics
+                .lines()
+                .any(|line| line.starts_with(name) && line.contains("template=\"example.typ\"")),
+            "missing {name} in:\n{metrics}"
+        );
+    }
+    assert!(metrics.contains("tws_renders_in_flight"));
+}
+
+#[tokio::test]
+/// A render that times out before it gets a limiter slot still counts as a
+/// failure of its template, and blocking pool usage is always reported.
+async fn metrics_count_handler_timeouts_per_template() {
+    logging::init_for_tests();
+    let limiter = RenderLimiter::new(std::num::NonZeroUsize::MIN);
+    let router = build_router_with_options(&ServerOptions {
+        render_timeout: Some(std::time::Duration::ZERO),
+        render_limiter: Some(limiter.clone()),
+        ..Default::default()
+    });
+    let _busy = limiter.acquire().await;
+
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/render-png/example.typ/timeout.png?page=1")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"World","list":["Test"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+    // Listing fonts runs on the blocking pool too.
+    let response = router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/fonts")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = router
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let bytes = body::to_bytes(response.into_body(), 10 * 1024 * 1024)
+        .await
+        .unwrap();
+    let metrics = String::from_utf8(bytes.to_vec()).unwrap();
+
+    assert!(
+        metrics
+            .lines()
+            .any(|line| line.starts_with("tws_render_failures_total")
+                && line.contains("template=\"example.typ\"")
+                && line.contains("format=\"png\"")
+                && line.contains("error=\"render_timeout\"")),
+        "missing timeout failure in:\n{metrics}"
+    );
+    assert!(metrics.contains("tws_blocking_tasks "));
+    assert!(metrics.contains("tws_blocking_tasks_queued "));
 }
@@ -0,0 +1,340 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Url, header::CONTENT_TYPE, redirect::Policy};
+use sha2::Sha256;
+use tracing::{debug, error, warn};
+
+use crate::error::{AppError, AppResult};
+
+/// Request header carrying the HMAC-SHA256 of `{timestamp}.{body}` as
+/// `sha256=<hex>`.
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+/// Request header carrying the Unix time the signature was made at, so
+/// receivers can reject replayed callbacks.
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+
+/// How completion callbacks are delivered.
+#[derive(Debug, Clone)]
+pub struct WebhookOptions {
+    /// Key the callback bodies are signed with; unsigned when `None`.
+    pub secret: Option<String>,
+    /// Further attempts after a failed delivery.
+    pub retries: u32,
+    /// Delay before the first retry; it doubles with every further retry.
+    pub backoff: Duration,
+    /// Time limit for a single attempt.
+    pub timeout: Duration,
+    /// Public base URL of the service, prefixed to the download location in
+    /// callbacks, e.g. `https://pdf.example.com`.
+    pub base_url: Option<String>,
+    /// Host names callbacks may be sent to, compared case-insensitively; any
+    /// host when empty.
+    pub allowed_hosts: Vec<String>,
+    /// Also send callbacks to loopback, private, and link-local addresses.
+    /// Off by default, so callers cannot reach internal services.
+    pub allow_private: bool,
+}
+
+impl Default for WebhookOptions {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            retries: 3,
+            backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+            base_url: None,
+            allowed_hosts: Vec::new(),
+            allow_private: false,
+        }
+    }
+}
+
+/// Sends signed callbacks with retries.
+#[derive(Clone)]
+pub(crate) struct Webhook {
+    options: WebhookOptions,
+}
+
+impl Webhook {
+    pub(crate) fn new(options: WebhookOptions) -> Self {
+        Self { options }
+    }
+
+    /// Check that a caller-supplied callback URL is an absolute HTTP(S) URL
+    /// to an allowed host.
+    ///
+    /// Host names are only resolved when the callback is sent; see
+    /// [`Webhook::connect`].
+    pub(crate) fn parse_url(&self, url: &str) -> AppResult<Url> {
+        let invalid =
+            |reason: &str| AppError::InvalidRenderOptions(format!("callback `{url}` {reason}"));
+        let parsed = Url::parse(url)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .ok_or_else(|| invalid("is not an http(s) URL"))?;
+        let host = parsed.host_str().ok_or_else(|| invalid("has no host"))?;
+
+        let allowed = &self.options.allowed_hosts;
+        if !allowed.is_empty()
+            && !allowed
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host))
+        {
+            return Err(invalid("is not on an allowed host"));
+        }
+        if let Some(ip) = literal_ip(&parsed)
+            && !self.options.allow_private
+            && !is_public(ip)
+        {
+            return Err(invalid("points to a private address"));
+        }
+        Ok(parsed)
+    }
+
+    /// Resolve the callback's host and build a client pinned to the
+    /// resolved addresses, so a second lookup cannot swap in a private one.
+    ///
+    /// Redirects are not followed, since they could lead anywhere.
+    pub(crate) async fn connect(&self, url: &Url) -> AppResult<Client> {
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addresses = match literal_ip(url) {
+            Some(ip) => vec![SocketAddr::new(ip, port)],
+            None => tokio::net::lookup_host((host, port)).await?.collect(),
+        };
+        if !self.options.allow_private
+            && let Some(address) = addresses.iter().find(|address| !is_public(address.ip()))
+        {
+            return Err(AppError::InvalidRenderOptions(format!(
+                "callback host `{host}` resolves to the private address {}",
+                address.ip()
+            )));
+        }
+
+        let mut builder = Client::builder().redirect(Policy::none());
+        if literal_ip(url).is_none() {
+            builder = builder.resolve_to_addrs(host, &addresses);
+        }
+        builder.build().map_err(|error| {
+            AppError::Io(std::io::Error::other(format!(
+                "failed to build webhook client: {error}"
+            )))
+        })
+    }
+
+    /// Prefix `path` with the configured public base URL, if any.
+    pub(crate) fn location(&self, path: &str) -> String {
+        match &self.options.base_url {
+            Some(base_url) => format!("{}{path}", base_url.trim_end_matches('/')),
+            None => path.to_string(),
+        }
+    }
+
+    /// POST `body` as JSON to `url` until it is accepted with a 2xx status
+    /// or the retries run out. Returns whether the callback was delivered.
+    pub(crate) async fn deliver(&self, url: Url, body: Vec<u8>) -> bool {
+        let client = match self.connect(&url).await {
+            Ok(client) => client,
+            Err(error) => {
+                error!(%url, %error, "Refusing to send webhook");
+                return false;
+            }
+        };
+        let attempts = self.options.retries + 1;
+        let mut delay = self.options.backoff;
+
+        for attempt in 1..=attempts {
+            let mut request = client
+                .post(url.clone())
+                .timeout(self.options.timeout)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(secret) = &self.options.secret {
+                let timestamp = Utc::now().timestamp();
+                request = request
+                    .header(TIMESTAMP_HEADER, timestamp)
+                    .header(SIGNATURE_HEADER, sign(secret.as_bytes(), timestamp, &body));
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!(%url, attempt, "Delivered webhook");
+                    return true;
+                }
+                Ok(response) => {
+                    warn!(%url, attempt, status = %response.status(), "Webhook was rejected");
+                }
+                Err(error) => warn!(%url, attempt, %error, "Failed to send webhook"),
+            }
+
+            if attempt < attempts {
+                tokio::time::sleep(delay).await;
+                delay = delay.saturating_mul(2);
+            }
+        }
+
+        error!(%url, attempts, "Giving up on webhook");
+        false
+    }
+}
+
+/// The IP address of `url`'s host, if it is given as one.
+fn literal_ip(url: &Url) -> Option<IpAddr> {
+    let host = url.host_str()?;
+    // IPv6 hosts keep their brackets.
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    host.parse().ok()
+}
+
+/// Whether `ip` is reachable on the public internet, as opposed to loopback,
+/// private, link-local (e.g. cloud metadata), or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0
+        // Shared address space used for carrier-grade NAT.
+        || (a == 100 && (64..128).contains(&b))
+        // Reserved for future use.
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // NAT64 addresses embed the IPv4 address they translate to.
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., a, b, c, d] = ip.octets();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+    let first = segments[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local addresses.
+        || (first & 0xfe00) == 0xfc00
+        // Link-local addresses.
+        || (first & 0xffc0) == 0xfe80
+        // Documentation addresses.
+        || (first == 0x2001 && segments[1] == 0x0db8))
+}
+
+/// Compute the signature header value for `body` sent at `timestamp`.
+pub(crate) fn sign(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    let mut message = format!("{timestamp}.").into_bytes();
+    message.extend_from_slice(body);
+    hmac_sha256(secret, &message)
+}
+
+/// HMAC-SHA256 of `message` as `sha256=<hex>`.
+fn hmac_sha256(secret: &[u8], message: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    let digest = mac.finalize().into_bytes();
+    let hex = digest
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("sha256={hex}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Webhook, WebhookOptions, hmac_sha256, is_public, sign};
+
+    /// Signatures match RFC 4231 test case 2.
+    #[test]
+    fn sign_matches_reference_vector() {
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            sign(b"Jefe", 1700000000, b"{}"),
+            hmac_sha256(b"Jefe", b"1700000000.{}")
+        );
+    }
+
+    /// Only absolute HTTP(S) URLs to allowed, public hosts are accepted as
+    /// callbacks.
+    #[test]
+    fn callback_urls_must_be_http() {
+        let webhook = Webhook::new(WebhookOptions::default());
+        assert!(webhook.parse_url("https://example.com/hook").is_ok());
+        assert!(webhook.parse_url("ftp://example.com/hook").is_err());
+        assert!(webhook.parse_url("/relative").is_err());
+        assert!(webhook.parse_url("http://127.0.0.1/hook").is_err());
+        assert!(webhook.parse_url("http://169.254.169.254/latest").is_err());
+        assert!(webhook.parse_url("http://[::ffff:10.0.0.1]/hook").is_err());
+
+        let webhook = Webhook::new(WebhookOptions {
+            allowed_hosts: vec!["Hooks.Example.com".to_string()],
+            ..Default::default()
+        });
+        assert!(webhook.parse_url("https://hooks.example.com/hook").is_ok());
+        assert!(webhook.parse_url("https://example.com/hook").is_err());
+    }
+
+    /// Host names are checked after resolution, so a public-looking name for
+    /// a private address is refused too.
+    #[tokio::test]
+    async fn connect_refuses_private_addresses() {
+        let url = "http://localhost:9/hook".parse().unwrap();
+        let webhook = Webhook::new(WebhookOptions::default());
+        assert!(webhook.connect(&url).await.is_err());
+
+        let webhook = Webhook::new(WebhookOptions {
+            allow_private: true,
+            ..Default::default()
+        });
+        assert!(webhook.connect(&url).await.is_ok());
+    }
+
+    /// Reserved ranges are not public, ordinary addresses are.
+    #[test]
+    fn classifies_addresses() {
+        for private in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "2001:db8::1",
+            "64:ff9b::10.0.0.1",
+            "64:ff9b::127.0.0.1",
+            "64:ff9b::169.254.169.254",
+        ] {
+            assert!(!is_public(private.parse().unwrap()), "{private}");
+        }
+        for public in ["93.184.216.34", "2606:4700::1111", "64:ff9b::93.184.216.34"] {
+            assert!(is_public(public.parse().unwrap()), "{public}");
+        }
+    }
+}